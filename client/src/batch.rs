@@ -0,0 +1,229 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use clap::{arg, ArgMatches, Command};
+use futures::{stream, StreamExt};
+use grpc::retriever::{retriever_client::RetrieverClient, BlobReply, BlobRequest};
+use serde::{Deserialize, Serialize};
+use tonic::{transport::Channel, Code, Status};
+
+use crate::parse_data_root;
+
+pub fn command() -> Command {
+    Command::new("batch")
+        .about("Fetches every blob listed in a manifest")
+        .args(&[
+            arg!(-m --manifest <FILE> "CSV or JSON manifest of storage_root, epoch and optional quorum_id")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+            arg!(-d --"out-dir" <DIR> "Directory the blobs are written to")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+            arg!(--concurrency <N> "Blobs fetched at the same time")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("8"),
+            arg!(--retries <N> "Extra attempts for a failed blob")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("2"),
+            arg!(--"retry-backoff-ms" <MS> "Delay before retrying a failed blob")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1000"),
+            arg!(--report <FILE> "Write the report as JSON as well")
+                .value_parser(clap::value_parser!(PathBuf)),
+        ])
+}
+
+/// Manifest entry, a CSV row or an element of a JSON array.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Entry {
+    pub storage_root: String,
+    pub epoch: u64,
+    pub quorum_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ItemReport {
+    storage_root: String,
+    epoch: u64,
+    quorum_id: Option<u64>,
+    ok: bool,
+    attempts: u32,
+    latency_ms: u128,
+    size: usize,
+    file: Option<PathBuf>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    total: usize,
+    succeeded: usize,
+    failed: usize,
+    elapsed_ms: u128,
+    items: Vec<ItemReport>,
+}
+
+pub async fn run(server: String, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let manifest = matches.get_one::<PathBuf>("manifest").expect("required");
+    let out_dir = matches.get_one::<PathBuf>("out-dir").expect("required");
+    let concurrency = *matches
+        .get_one::<usize>("concurrency")
+        .expect("has default");
+    let retries = *matches.get_one::<u32>("retries").expect("has default");
+    let retry_backoff = Duration::from_millis(
+        *matches
+            .get_one::<u64>("retry-backoff-ms")
+            .expect("has default"),
+    );
+
+    let entries = read_manifest(manifest)?;
+    fs::create_dir_all(out_dir)?;
+
+    let ts = Instant::now();
+    let client = crate::connect(server, matches).await?;
+    let items: Vec<ItemReport> = stream::iter(entries)
+        .map(|entry| fetch(client.clone(), entry, out_dir, retries, retry_backoff))
+        .buffer_unordered(concurrency.max(1))
+        .inspect(|item| match &item.error {
+            None => eprintln!(
+                "ok     {} epoch {} in {} ms",
+                item.storage_root, item.epoch, item.latency_ms
+            ),
+            Some(e) => eprintln!(
+                "failed {} epoch {} after {} attempts: {}",
+                item.storage_root, item.epoch, item.attempts, e
+            ),
+        })
+        .collect()
+        .await;
+
+    let succeeded = items.iter().filter(|item| item.ok).count();
+    let report = Report {
+        total: items.len(),
+        succeeded,
+        failed: items.len() - succeeded,
+        elapsed_ms: ts.elapsed().as_millis(),
+        items,
+    };
+    eprintln!(
+        "{} blobs, {} succeeded, {} failed in {} ms",
+        report.total, report.succeeded, report.failed, report.elapsed_ms
+    );
+
+    if let Some(path) = matches.get_one::<PathBuf>("report") {
+        fs::write(path, serde_json::to_vec_pretty(&report)?)?;
+    }
+
+    if report.failed > 0 {
+        return Err(format!("{} of {} blobs failed", report.failed, report.total).into());
+    }
+    Ok(())
+}
+
+/// Reads a JSON manifest if the file ends in `.json`, a CSV one with a
+/// `storage_root,epoch,quorum_id` header otherwise.
+pub fn read_manifest(path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let content = fs::read(path)?;
+    if path.extension().map_or(false, |ext| ext == "json") {
+        return Ok(serde_json::from_slice(&content)?);
+    }
+
+    let mut entries = vec![];
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(content.as_slice());
+    for entry in reader.deserialize() {
+        entries.push(entry?);
+    }
+    Ok(entries)
+}
+
+async fn fetch(
+    mut client: RetrieverClient<Channel>,
+    entry: Entry,
+    out_dir: &Path,
+    retries: u32,
+    retry_backoff: Duration,
+) -> ItemReport {
+    let ts = Instant::now();
+    let mut report = ItemReport {
+        storage_root: entry.storage_root.clone(),
+        epoch: entry.epoch,
+        quorum_id: entry.quorum_id,
+        ok: false,
+        attempts: 0,
+        latency_ms: 0,
+        size: 0,
+        file: None,
+        error: None,
+    };
+
+    let storage_root = match parse_data_root(&entry.storage_root) {
+        Ok(x) => x,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+
+    let mut res: Result<BlobReply, Status> = Err(Status::new(Code::Unknown, "not attempted"));
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            tokio::time::sleep(retry_backoff).await;
+        }
+
+        report.attempts += 1;
+        res = client
+            .retrieve_blob(BlobRequest::new(
+                storage_root.clone(),
+                entry.epoch,
+                entry.quorum_id,
+            ))
+            .await
+            .map(|reply| reply.into_inner());
+        match &res {
+            Ok(_) => break,
+            // the same request fails the same way
+            Err(status) if !is_retryable(status.code()) => break,
+            Err(_) => {}
+        }
+    }
+    report.latency_ms = ts.elapsed().as_millis();
+
+    let reply = match res {
+        Ok(x) => x,
+        Err(status) => {
+            report.error = Some(format!("{:?}: {}", status.code(), status.message()));
+            return report;
+        }
+    };
+
+    let file = out_dir.join(format!(
+        "{}_{}_{}.blob",
+        hex::encode(&storage_root),
+        entry.epoch,
+        reply
+            .quorum_id
+            .map_or_else(|| "any".to_string(), |id| id.to_string())
+    ));
+    match tokio::fs::write(&file, &reply.data).await {
+        Ok(()) => {
+            report.ok = true;
+            report.size = reply.data.len();
+            report.file = Some(file);
+        }
+        Err(e) => report.error = Some(format!("failed to write {:?}: {}", file, e)),
+    }
+    report
+}
+
+fn is_retryable(code: Code) -> bool {
+    !matches!(
+        code,
+        Code::InvalidArgument | Code::OutOfRange | Code::Unimplemented
+    )
+}
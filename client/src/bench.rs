@@ -0,0 +1,261 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::{arg, ArgMatches, Command};
+use grpc::retriever::BlobRequest;
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{batch, parse_data_root};
+
+pub fn command() -> Command {
+    Command::new("bench")
+        .about("Replays the blob requests of a manifest against a retriever")
+        .args(&[
+            arg!(-m --manifest <FILE> "CSV or JSON manifest of the requests, replayed in a loop")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+            arg!(--concurrency <N> "Requests in flight at most")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("8"),
+            arg!(--rate <PER_SEC> "Start requests at this rate instead of as fast as the concurrency allows, dropping those finding no free slot")
+                .value_parser(clap::value_parser!(f64)),
+            arg!(--requests <N> "Stop after this many requests, one pass over the manifest by default")
+                .value_parser(clap::value_parser!(usize)),
+            arg!(--"duration-secs" <SECS> "Stop starting requests after this time")
+                .value_parser(clap::value_parser!(u64))
+                .conflicts_with("requests"),
+            arg!(--json "Print the report as JSON"),
+        ])
+}
+
+struct Sample {
+    latency: Duration,
+    // `None` on success
+    code: Option<tonic::Code>,
+    size: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyReport {
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    requests: usize,
+    succeeded: usize,
+    elapsed_secs: f64,
+    throughput_per_sec: f64,
+    throughput_bytes_per_sec: f64,
+    /// Latency of the successful requests.
+    latency: Option<LatencyReport>,
+    /// Failed requests by gRPC code.
+    errors: BTreeMap<String, usize>,
+    /// With `--rate`, scheduled requests not sent as `--concurrency` requests
+    /// were in flight.
+    dropped: usize,
+    /// With `--rate`, requests sent more than one interval after their
+    /// schedule, the client falling behind.
+    late: usize,
+}
+
+pub async fn run(server: String, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let manifest = matches.get_one::<PathBuf>("manifest").expect("required");
+    let concurrency = *matches
+        .get_one::<usize>("concurrency")
+        .expect("has default");
+    let rate = matches.get_one::<f64>("rate").copied();
+    let duration = matches
+        .get_one::<u64>("duration-secs")
+        .map(|secs| Duration::from_secs(*secs));
+
+    let mut requests = vec![];
+    for entry in batch::read_manifest(manifest)? {
+        requests.push(BlobRequest::new(
+            parse_data_root(&entry.storage_root)?,
+            entry.epoch,
+            entry.quorum_id,
+        ));
+    }
+    if requests.is_empty() {
+        return Err("manifest is empty".into());
+    }
+    let total = match (matches.get_one::<usize>("requests"), duration) {
+        (Some(n), _) => Some(*n),
+        (None, Some(_)) => None,
+        (None, None) => Some(requests.len()),
+    };
+
+    let client = crate::connect(server, matches).await?;
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let interval = match rate {
+        Some(rate) if rate > 0.0 => Some(Duration::from_secs_f64(1.0 / rate)),
+        Some(_) => return Err("rate must be positive".into()),
+        None => None,
+    };
+
+    let ts = Instant::now();
+    let mut tasks = JoinSet::new();
+    let (mut dropped, mut late) = (0, 0);
+    for i in 0.. {
+        if total.map_or(false, |total| i >= total)
+            || duration.map_or(false, |duration| ts.elapsed() >= duration)
+        {
+            break;
+        }
+
+        // with a rate, requests follow a fixed schedule whatever the server
+        // does and their latency counts from their slot in it, so that a
+        // slow server cannot hold back the requests that would expose it
+        let (scheduled, permit) = match interval {
+            Some(interval) => {
+                let scheduled = ts + interval.mul_f64(i as f64);
+                tokio::time::sleep_until(scheduled.into()).await;
+                if scheduled.elapsed() > interval {
+                    late += 1;
+                }
+                match semaphore.clone().try_acquire_owned() {
+                    Ok(permit) => (scheduled, permit),
+                    Err(_) => {
+                        dropped += 1;
+                        continue;
+                    }
+                }
+            }
+            None => {
+                let permit = semaphore.clone().acquire_owned().await?;
+                (Instant::now(), permit)
+            }
+        };
+
+        let request = requests[i % requests.len()].clone();
+        let mut client = client.clone();
+        tasks.spawn(async move {
+            let res = client.retrieve_blob(request).await;
+            drop(permit);
+
+            match res {
+                Ok(reply) => Sample {
+                    latency: scheduled.elapsed(),
+                    code: None,
+                    size: reply.into_inner().data.len(),
+                },
+                Err(status) => Sample {
+                    latency: scheduled.elapsed(),
+                    code: Some(status.code()),
+                    size: 0,
+                },
+            }
+        });
+    }
+
+    let mut samples = vec![];
+    while let Some(sample) = tasks.join_next().await {
+        samples.push(sample?);
+    }
+    let report = Report {
+        dropped,
+        late,
+        ..summarize(samples, ts.elapsed())
+    };
+
+    if matches.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(())
+}
+
+fn summarize(samples: Vec<Sample>, elapsed: Duration) -> Report {
+    let mut errors = BTreeMap::new();
+    let mut latencies = vec![];
+    let mut bytes = 0;
+    for sample in samples.iter() {
+        match sample.code {
+            None => {
+                latencies.push(sample.latency.as_secs_f64() * 1000.0);
+                bytes += sample.size;
+            }
+            Some(code) => *errors.entry(format!("{:?}", code)).or_insert(0) += 1,
+        }
+    }
+    latencies.sort_by(f64::total_cmp);
+
+    let latency = latencies.last().map(|max| LatencyReport {
+        p50_ms: percentile(&latencies, 0.50),
+        p95_ms: percentile(&latencies, 0.95),
+        p99_ms: percentile(&latencies, 0.99),
+        max_ms: *max,
+    });
+
+    let elapsed_secs = elapsed.as_secs_f64();
+    Report {
+        requests: samples.len(),
+        succeeded: latencies.len(),
+        elapsed_secs,
+        throughput_per_sec: latencies.len() as f64 / elapsed_secs,
+        throughput_bytes_per_sec: bytes as f64 / elapsed_secs,
+        latency,
+        errors,
+        dropped: 0,
+        late: 0,
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty values.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn print_report(report: &Report) {
+    println!(
+        "requests: {}, succeeded: {}, elapsed: {:.2} s",
+        report.requests, report.succeeded, report.elapsed_secs
+    );
+    println!(
+        "throughput: {:.2} req/s, {:.2} MiB/s",
+        report.throughput_per_sec,
+        report.throughput_bytes_per_sec / (1024.0 * 1024.0)
+    );
+    if let Some(latency) = report.latency.as_ref() {
+        println!(
+            "latency: p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+            latency.p50_ms, latency.p95_ms, latency.p99_ms, latency.max_ms
+        );
+    }
+    for (code, count) in report.errors.iter() {
+        println!("error {}: {}", code, count);
+    }
+    if report.dropped > 0 || report.late > 0 {
+        println!(
+            "dropped: {} (concurrency exhausted), late: {} (client behind schedule)",
+            report.dropped, report.late
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank_percentile() {
+        let values: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&values, 0.50), 50.0);
+        assert_eq!(percentile(&values, 0.99), 99.0);
+        assert_eq!(percentile(&values, 1.0), 100.0);
+        assert_eq!(percentile(&[7.0], 0.95), 7.0);
+    }
+}
@@ -0,0 +1,47 @@
+use std::{error::Error, sync::Arc};
+
+use contract_provider::{ContractProvider, EthRpcConfig};
+use grpc::{
+    retriever::{BlobReply, BlobRequest, ServedBy},
+    Retriever,
+};
+use signer_provider::{SignerConfig, SignerProvider};
+use tonic::Status;
+use tracing_subscriber::EnvFilter;
+
+/// Retrieval running in-process: reads the quorum from chain, asks the
+/// signers for slices and recovers the blob, without a retriever server.
+pub struct DirectRetriever {
+    retriever: Retriever,
+}
+
+impl DirectRetriever {
+    pub async fn new(eth_rpc_urls: Vec<String>) -> Result<Self, Box<dyn Error>> {
+        let contract_provider = ContractProvider::new(&EthRpcConfig::new(eth_rpc_urls)).await?;
+        let signer_provider = Arc::new(SignerProvider::new(SignerConfig::default())?);
+
+        Ok(Self {
+            retriever: Retriever::builder(Arc::new(contract_provider), signer_provider).build(),
+        })
+    }
+
+    pub async fn retrieve_blob(&self, request: BlobRequest) -> Result<BlobReply, Status> {
+        let blob = self
+            .retriever
+            .retrieve(&request.storage_root, request.epoch, request.quorum())
+            .await?;
+        Ok(BlobReply {
+            data: blob.data.into(),
+            quorum_id: blob.quorum_id,
+            served_by: ServedBy::from(blob.source).into(),
+        })
+    }
+}
+
+/// Logs of the in-process retrieval go to stderr, filtered by `RUST_LOG`.
+pub fn init_logging() {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+}
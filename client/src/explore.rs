@@ -0,0 +1,261 @@
+use std::{collections::BTreeSet, error::Error, fs, path::PathBuf, str::FromStr, time::Duration};
+
+use clap::{arg, ArgMatches, Command};
+use contract_provider::{ContractProvider, EthRpcConfig, SignerDetail};
+use ethers::types::{H160, U256};
+use futures::future;
+use signer_provider::{SignerConfig, SignerProvider};
+
+pub fn commands() -> Vec<Command> {
+    vec![
+        Command::new("quorum")
+            .about("Lists the rows owned by each signer of a quorum")
+            .args(quorum_args()),
+        Command::new("signer")
+            .about("Shows the socket and public keys of a signer")
+            .arg(arg!(<ADDRESS> "Signer address")),
+        Command::new("ping-signers")
+            .about("Probes the socket of every signer of a quorum")
+            .args(quorum_args())
+            .arg(
+                arg!(--"timeout-ms" <MS> "Timeout of a probe")
+                    .value_parser(clap::value_parser!(u64))
+                    .default_value("5000"),
+            ),
+        Command::new("export-registry")
+            .about("Saves the quorums and signers of some epochs to a JSON registry snapshot")
+            .args(&[
+                arg!(--"from-epoch" <NUM> "First epoch exported, the current one by default")
+                    .value_parser(clap::value_parser!(u64)),
+                arg!(--"to-epoch" <NUM> "Last epoch exported, `--from-epoch` by default")
+                    .value_parser(clap::value_parser!(u64)),
+                arg!(--out <FILE> "Snapshot file")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf)),
+            ]),
+    ]
+}
+
+fn quorum_args() -> [clap::Arg; 2] {
+    [
+        arg!(-e --epoch <NUM> "Set epoch")
+            .required(true)
+            .value_parser(clap::value_parser!(u64)),
+        arg!(-q --"quorum-id" <ID> "Set quorum id")
+            .required(true)
+            .value_parser(clap::value_parser!(u64)),
+    ]
+}
+
+pub async fn run_quorum(
+    eth_rpc_urls: Vec<String>,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let (epoch, quorum_id) = epoch_and_quorum_id(matches);
+    let contract_provider = ContractProvider::new(&EthRpcConfig::new(eth_rpc_urls)).await?;
+    let quorum = contract_provider.get_signers(epoch, quorum_id).await?;
+
+    // rows of signers missing from `getSigner` are listed as well
+    let addresses: BTreeSet<H160> = quorum
+        .systematic_rows
+        .keys()
+        .chain(quorum.parity_rows.keys())
+        .chain(quorum.signers.keys())
+        .copied()
+        .collect();
+    let unknown = addresses
+        .iter()
+        .filter(|address| !quorum.signers.contains_key(address))
+        .count();
+
+    println!(
+        "epoch {}, quorum {}: {} rows, {} systematic, {} signers, {} unknown",
+        epoch,
+        quorum_id,
+        quorum.params.encoded_slices,
+        quorum.params.systematic_rows,
+        addresses.len(),
+        unknown
+    );
+
+    for address in addresses {
+        let systematic = quorum
+            .systematic_rows
+            .get(&address)
+            .cloned()
+            .unwrap_or_default();
+        let parity = quorum
+            .parity_rows
+            .get(&address)
+            .cloned()
+            .unwrap_or_default();
+
+        match quorum.signers.get(&address) {
+            Some(signer) => println!("{:?} {}", address, signer.socket),
+            None => println!("{:?} unknown signer, no details on chain", address),
+        }
+        println!(
+            "  systematic rows ({}): {}",
+            systematic.len(),
+            format_rows(systematic)
+        );
+        println!("  parity rows ({}): {}", parity.len(), format_rows(parity));
+    }
+
+    Ok(())
+}
+
+pub async fn run_signer(
+    eth_rpc_urls: Vec<String>,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let address = H160::from_str(matches.get_one::<String>("ADDRESS").expect("required"))?;
+    let contract_provider = ContractProvider::new(&EthRpcConfig::new(eth_rpc_urls)).await?;
+    let signer = contract_provider.get_signer(address).await?;
+
+    println!("signer: {:?}", signer.signer);
+    println!("socket: {}", signer.socket);
+    println!("pk_g1.x: {}", hex_u256(signer.pk_g1.x));
+    println!("pk_g1.y: {}", hex_u256(signer.pk_g1.y));
+    println!(
+        "pk_g2.x: [{}, {}]",
+        hex_u256(signer.pk_g2.x[0]),
+        hex_u256(signer.pk_g2.x[1])
+    );
+    println!(
+        "pk_g2.y: [{}, {}]",
+        hex_u256(signer.pk_g2.y[0]),
+        hex_u256(signer.pk_g2.y[1])
+    );
+
+    Ok(())
+}
+
+pub async fn run_ping_signers(
+    eth_rpc_urls: Vec<String>,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let (epoch, quorum_id) = epoch_and_quorum_id(matches);
+    let timeout =
+        Duration::from_millis(*matches.get_one::<u64>("timeout-ms").expect("has default"));
+
+    let contract_provider = ContractProvider::new(&EthRpcConfig::new(eth_rpc_urls)).await?;
+    let quorum = contract_provider.get_signers(epoch, quorum_id).await?;
+    let signer_provider = SignerProvider::new(SignerConfig {
+        connect_timeout: timeout,
+        request_timeout: timeout,
+        ..Default::default()
+    })?;
+
+    let mut signers: Vec<&SignerDetail> = quorum.signers.values().collect();
+    signers.sort_by_key(|signer| signer.signer);
+    let results = future::join_all(signers.iter().map(|signer| {
+        let socket = signer_provider::socket_url(&signer.socket);
+        let signer_provider = &signer_provider;
+        async move { signer_provider.ping(&socket).await }
+    }))
+    .await;
+
+    let mut reachable = 0;
+    for (signer, res) in signers.iter().zip(results) {
+        match res {
+            Ok(latency) => {
+                reachable += 1;
+                println!(
+                    "{:?} {} ok {} ms",
+                    signer.signer,
+                    signer.socket,
+                    latency.as_millis()
+                );
+            }
+            Err(e) => println!("{:?} {} unreachable: {}", signer.signer, signer.socket, e),
+        }
+    }
+    eprintln!("{} of {} signers reachable", reachable, signers.len());
+
+    Ok(())
+}
+
+pub async fn run_export_registry(
+    eth_rpc_urls: Vec<String>,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let contract_provider = ContractProvider::new(&EthRpcConfig::new(eth_rpc_urls)).await?;
+    let from_epoch = match matches.get_one::<u64>("from-epoch") {
+        Some(epoch) => *epoch,
+        None => contract_provider.current_epoch().await?,
+    };
+    let to_epoch = matches
+        .get_one::<u64>("to-epoch")
+        .copied()
+        .unwrap_or(from_epoch);
+    if to_epoch < from_epoch {
+        return Err("`--to-epoch` is before `--from-epoch`".into());
+    }
+
+    let snapshot = contract_provider
+        .export_snapshot(from_epoch..=to_epoch)
+        .await?;
+    let out = matches.get_one::<PathBuf>("out").expect("required");
+    fs::write(out, serde_json::to_vec_pretty(&snapshot)?)?;
+    eprintln!(
+        "exported epochs {}-{}: {} quorums, {} signers to {}",
+        from_epoch,
+        to_epoch,
+        snapshot.quorums.values().map(Vec::len).sum::<usize>(),
+        snapshot.signers.len(),
+        out.display()
+    );
+
+    Ok(())
+}
+
+fn epoch_and_quorum_id(matches: &ArgMatches) -> (u64, u64) {
+    (
+        *matches.get_one::<u64>("epoch").expect("required"),
+        *matches.get_one::<u64>("quorum-id").expect("required"),
+    )
+}
+
+fn hex_u256(value: U256) -> String {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// Compacts sorted row indices into ranges, e.g. `0-2,5`.
+fn format_rows(mut rows: Vec<u32>) -> String {
+    rows.sort_unstable();
+
+    let mut ranges: Vec<(u32, u32)> = vec![];
+    for row in rows {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == row => *end = row,
+            _ => ranges.push((row, row)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_rows_as_ranges() {
+        assert_eq!(format_rows(vec![5, 0, 1, 2, 7, 8]), "0-2,5,7-8");
+        assert_eq!(format_rows(vec![3]), "3");
+        assert_eq!(format_rows(vec![]), "");
+    }
+}
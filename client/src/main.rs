@@ -1,7 +1,19 @@
-use std::error::Error;
+mod batch;
+mod bench;
+mod direct;
+mod explore;
+mod output;
 
-use clap::{arg, command};
+use std::{error::Error, path::PathBuf, time::Instant};
+
+use clap::{arg, command, ArgAction, ArgMatches};
+use direct::DirectRetriever;
 use grpc::retriever::{retriever_client::RetrieverClient, BlobRequest};
+use output::Format;
+use tonic::transport::Channel;
+
+/// Same as the retriever server's default.
+const DEFAULT_MAX_MESSAGE_SIZE: &str = "1073741824";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -12,37 +24,125 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .args(&[
             arg!(-r --"data-root" <HASH> "Set data root"),
             arg!(-e --epoch <NUM> "Set epoch").value_parser(clap::value_parser!(u64)),
-            arg!(-q --"quorum-id" <ID> "Set quorum id").value_parser(clap::value_parser!(u64)),
-            arg!(-s --server <URL> "Retriever server address"),
+            arg!(-q --"quorum-id" <ID> "Set quorum id, all quorums of the epoch are tried if omitted").value_parser(clap::value_parser!(u64)),
+            arg!(-s --server <URL> "Retriever server address").global(true),
+            arg!(--"max-message-size" <BYTES> "Largest reply accepted from the retriever server")
+                .value_parser(clap::value_parser!(usize))
+                .default_value(DEFAULT_MAX_MESSAGE_SIZE)
+                .global(true),
+            arg!(-o --output <FILE> "Write the blob to a file instead of stdout")
+                .value_parser(clap::value_parser!(PathBuf)),
+            arg!(-f --format <FORMAT> "Output format of the blob")
+                .value_parser(clap::value_parser!(Format))
+                .default_value("raw"),
+            arg!(--checksum "Print the SHA-256 of the blob to stderr"),
+            arg!(--direct "Fetch from the signers in-process instead of through a retriever server")
+                .requires("eth-rpc")
+                .conflicts_with("server"),
+            arg!(--"eth-rpc" <URL> "JSON RPC endpoint used with --direct and the chain queries, repeat to fail over")
+                .action(ArgAction::Append)
+                .global(true),
         ])
-        .allow_external_subcommands(true)
+        .subcommand(batch::command())
+        .subcommand(bench::command())
+        .subcommands(explore::commands())
         .get_matches();
 
-    let data_root = hex::decode(
-        &matches
+    match matches.subcommand() {
+        Some(("batch", sub)) => batch::run(server(&matches), sub).await,
+        Some(("bench", sub)) => bench::run(server(&matches), sub).await,
+        Some(("quorum", sub)) => explore::run_quorum(eth_rpc_urls(&matches), sub).await,
+        Some(("signer", sub)) => explore::run_signer(eth_rpc_urls(&matches), sub).await,
+        Some(("ping-signers", sub)) => explore::run_ping_signers(eth_rpc_urls(&matches), sub).await,
+        Some(("export-registry", sub)) => {
+            explore::run_export_registry(eth_rpc_urls(&matches), sub).await
+        }
+        _ => retrieve(&matches).await,
+    }
+}
+
+fn server(matches: &ArgMatches) -> String {
+    matches
+        .get_one::<String>("server")
+        .expect("server must provide")
+        .clone()
+}
+
+/// Connects to the retriever server, accepting replies up to
+/// `--max-message-size` bytes.
+async fn connect(
+    server: String,
+    matches: &ArgMatches,
+) -> Result<RetrieverClient<Channel>, Box<dyn Error>> {
+    let max_message_size = *matches
+        .get_one::<usize>("max-message-size")
+        .expect("has default");
+    Ok(RetrieverClient::connect(server)
+        .await?
+        .max_decoding_message_size(max_message_size))
+}
+
+fn eth_rpc_urls(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .get_many::<String>("eth-rpc")
+        .expect("eth rpc must provide")
+        .cloned()
+        .collect()
+}
+
+async fn retrieve(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_root = parse_data_root(
+        matches
             .get_one::<String>("data-root")
-            .expect("data root must provide")[2..],
+            .expect("data root must provide"),
     )?;
     let epoch = *matches.get_one::<u64>("epoch").expect("epoch must provide");
-    let quorum_id = *matches
-        .get_one::<u64>("quorum-id")
-        .expect("quorum id must provide");
-    let server = matches
-        .get_one::<String>("server")
-        .expect("server must provide")
-        .clone();
+    // every quorum of the epoch is tried when no quorum id is given
+    let quorum_id = matches.get_one::<u64>("quorum-id").copied();
+    let output = matches.get_one::<PathBuf>("output");
+    let format = *matches.get_one::<Format>("format").expect("has default");
+
+    // only the blob goes to stdout, so that it can be piped
+    let ts = Instant::now();
+    let request = BlobRequest::new(data_root, epoch, quorum_id);
+    let reply = if matches.get_flag("direct") {
+        direct::init_logging();
+        let retriever = DirectRetriever::new(eth_rpc_urls(matches)).await?;
+        retriever.retrieve_blob(request).await
+    } else {
+        let mut client = connect(server(matches), matches).await?;
+        client
+            .retrieve_blob(request)
+            .await
+            .map(|reply| reply.into_inner())
+    };
+    let reply = reply.map_err(|status| {
+        format!(
+            "retrieve failed, code: {:?}, message: {}",
+            status.code(),
+            status.message()
+        )
+    })?;
 
-    let mut client = RetrieverClient::connect(server).await.unwrap();
-    let reply = client
-        .retrieve_blob(BlobRequest {
-            epoch,
-            quorum_id,
-            storage_root: data_root,
-        })
-        .await
-        .unwrap();
+    eprintln!(
+        "retrieved {} bytes from quorum {}, served by {:?}, in {} ms",
+        reply.data.len(),
+        reply
+            .quorum_id
+            .map_or_else(|| "unknown".to_string(), |id| id.to_string()),
+        reply.served_by(),
+        ts.elapsed().as_millis()
+    );
+    if matches.get_flag("checksum") {
+        eprintln!("sha256: {}", output::checksum(&reply.data));
+    }
 
-    println!("reply: {:?}", reply);
+    output::write_blob(&reply.data, format, output.map(PathBuf::as_path))?;
 
     Ok(())
 }
+
+/// Decodes a hex storage root, `0x` prefixed or not.
+fn parse_data_root(s: &str) -> Result<Vec<u8>, hex::FromHexError> {
+    hex::decode(s.strip_prefix("0x").unwrap_or(s))
+}
@@ -0,0 +1,60 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Blob bytes as is.
+    Raw,
+    /// Lowercase hex, newline terminated.
+    Hex,
+    /// Standard base64 with padding, newline terminated.
+    Base64,
+}
+
+impl Format {
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Format::Raw => data.to_vec(),
+            Format::Hex => format!("{}\n", hex::encode(data)).into_bytes(),
+            Format::Base64 => format!("{}\n", STANDARD.encode(data)).into_bytes(),
+        }
+    }
+}
+
+/// Writes the blob to `output`, or to stdout if not given.
+pub fn write_blob(data: &[u8], format: Format, output: Option<&Path>) -> io::Result<()> {
+    let encoded = format.encode(data);
+    match output {
+        Some(path) => fs::write(path, encoded),
+        None => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&encoded)?;
+            stdout.flush()
+        }
+    }
+}
+
+/// Hex SHA-256 of the blob, comparable with `sha256sum` of a raw output.
+pub fn checksum(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_formats() {
+        let data = [0u8, 1, 0xfe, 0xff];
+        assert_eq!(Format::Raw.encode(&data), data.to_vec());
+        assert_eq!(Format::Hex.encode(&data), b"0001feff\n".to_vec());
+        assert_eq!(Format::Base64.encode(&data), b"AAH+/w==\n".to_vec());
+    }
+}
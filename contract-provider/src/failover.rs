@@ -0,0 +1,320 @@
+use std::{
+    fmt::Debug,
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, HttpClientError, HttpRateLimitRetryPolicy, JsonRpcClient, JsonRpcError, ProviderError,
+    RetryPolicy, RpcError,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+use tokio::time::error::Elapsed;
+
+/// Settings of the eth JSON-RPC endpoints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EthRpcConfig {
+    /// Endpoints in order of preference.
+    pub urls: Vec<String>,
+    /// Timeout of a single request to one endpoint.
+    pub request_timeout: Duration,
+    /// How long a failed endpoint is only used as a last resort.
+    pub cooldown: Duration,
+    /// Interval of the background health probes.
+    pub health_check_interval: Duration,
+    /// Extra passes over all endpoints before a request fails.
+    pub retries: usize,
+    /// Delay between two passes over all endpoints, also the initial backoff
+    /// of a rate limited endpoint.
+    pub retry_backoff: Duration,
+    /// Retries of a rate limited endpoint before failing over, with an
+    /// exponential backoff.
+    pub rate_limit_retries: usize,
+    /// Compare `getQuorum` results of two endpoints.
+    pub cross_check_quorum: bool,
+    /// How long the erasure coding params read from chain are cached.
+    pub params_cache_ttl: Duration,
+}
+
+impl EthRpcConfig {
+    pub fn new(urls: Vec<String>) -> Self {
+        Self {
+            urls,
+            request_timeout: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+            health_check_interval: Duration::from_secs(15),
+            retries: 2,
+            retry_backoff: Duration::from_millis(500),
+            rate_limit_retries: 3,
+            cross_check_quorum: false,
+            params_cache_ttl: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FailoverError {
+    #[error(transparent)]
+    Http(#[from] HttpClientError),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+    #[error("all eth rpc endpoints failed, last error: {0}")]
+    Unavailable(String),
+}
+
+impl RpcError for FailoverError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            FailoverError::Http(e) => e.as_error_response(),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            FailoverError::Http(e) => e.as_serde_error(),
+            FailoverError::Serde(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<FailoverError> for ProviderError {
+    fn from(src: FailoverError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    client: Http,
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn is_healthy(&self) -> bool {
+        match *self.unhealthy_until.lock().unwrap() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    fn mark_healthy(&self) {
+        *self.unhealthy_until.lock().unwrap() = None;
+    }
+
+    fn mark_unhealthy(&self, cooldown: Duration) {
+        *self.unhealthy_until.lock().unwrap() = Some(Instant::now() + cooldown);
+    }
+}
+
+/// JSON-RPC client sending each request to the first healthy endpoint and
+/// failing over to the next one on errors or timeouts.
+#[derive(Debug)]
+pub struct FailoverClient {
+    endpoints: Arc<Vec<Endpoint>>,
+    request_timeout: Duration,
+    cooldown: Duration,
+    retries: usize,
+    retry_backoff: Duration,
+    rate_limit_retries: usize,
+    health_check_interval: Duration,
+}
+
+impl FailoverClient {
+    pub fn new(config: &EthRpcConfig) -> Result<Self> {
+        if config.urls.is_empty() {
+            bail!("no eth rpc endpoint configured");
+        }
+
+        let mut endpoints = vec![];
+        for url in config.urls.iter() {
+            endpoints.push(Endpoint {
+                url: url.clone(),
+                client: Http::from_str(url)?,
+                unhealthy_until: Mutex::new(None),
+            });
+        }
+
+        Ok(Self {
+            endpoints: Arc::new(endpoints),
+            request_timeout: config.request_timeout,
+            cooldown: config.cooldown,
+            retries: config.retries,
+            retry_backoff: config.retry_backoff,
+            rate_limit_retries: config.rate_limit_retries,
+            health_check_interval: config.health_check_interval,
+        })
+    }
+
+    /// Endpoint indices to try, healthy ones first.
+    fn candidates(&self) -> Vec<usize> {
+        let (mut healthy, unhealthy): (Vec<usize>, Vec<usize>) =
+            (0..self.endpoints.len()).partition(|i| self.endpoints[*i].is_healthy());
+        healthy.extend(unhealthy);
+        healthy
+    }
+
+    /// Sends a request like [`JsonRpcClient::request`], but collects the
+    /// answers of `count` distinct endpoints, each paired with its url.
+    pub async fn request_distinct<T, R>(
+        &self,
+        method: &str,
+        params: T,
+        count: usize,
+    ) -> Result<Vec<(String, R)>, FailoverError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        if count > self.endpoints.len() {
+            return Err(FailoverError::Unavailable(format!(
+                "{} endpoints required, {} configured",
+                count,
+                self.endpoints.len()
+            )));
+        }
+        let params = serde_json::to_value(params)?;
+
+        let mut answers = vec![];
+        let mut answered = vec![false; self.endpoints.len()];
+        let mut last_err = String::new();
+        for round in 0..=self.retries {
+            if round > 0 {
+                tokio::time::sleep(self.retry_backoff).await;
+            }
+
+            for i in self.candidates() {
+                if answered[i] {
+                    continue;
+                }
+                let endpoint = &self.endpoints[i];
+                match self.request_endpoint::<R>(endpoint, method, &params).await {
+                    Ok(Ok(r)) => {
+                        endpoint.mark_healthy();
+                        answered[i] = true;
+                        answers.push((endpoint.url.clone(), r));
+                        if answers.len() >= count {
+                            return Ok(answers);
+                        }
+                    }
+                    // the node answered, other endpoints would answer the same,
+                    // unless it is rate limiting
+                    Ok(Err(e))
+                        if e.is_error_response() && !HttpRateLimitRetryPolicy.should_retry(&e) =>
+                    {
+                        return Err(e.into())
+                    }
+                    Ok(Err(e)) => {
+                        warn!(url = %endpoint.url, method, "eth rpc request failed, error: {:?}", e);
+                        endpoint.mark_unhealthy(self.cooldown);
+                        last_err = e.to_string();
+                    }
+                    Err(_) => {
+                        warn!(url = %endpoint.url, method, "eth rpc request timed out");
+                        endpoint.mark_unhealthy(self.cooldown);
+                        last_err = format!("{} timed out", endpoint.url);
+                    }
+                }
+            }
+        }
+
+        Err(FailoverError::Unavailable(last_err))
+    }
+
+    /// Sends a request to one endpoint, retrying with an exponential backoff
+    /// while it is rate limited. Times out as a whole after the request
+    /// timeout.
+    async fn request_endpoint<R: DeserializeOwned + Send>(
+        &self,
+        endpoint: &Endpoint,
+        method: &str,
+        params: &Value,
+    ) -> Result<Result<R, HttpClientError>, Elapsed> {
+        tokio::time::timeout(self.request_timeout, async {
+            let mut backoff = self.retry_backoff;
+            let mut retries = 0;
+            loop {
+                match endpoint.client.request::<_, R>(method, params).await {
+                    Err(e)
+                        if retries < self.rate_limit_retries
+                            && HttpRateLimitRetryPolicy.should_retry(&e) =>
+                    {
+                        debug!(url = %endpoint.url, method, ?backoff, "eth rpc rate limited");
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                        retries += 1;
+                    }
+                    res => return res,
+                }
+            }
+        })
+        .await
+    }
+
+    /// Probes the endpoints in the background until the client is dropped.
+    /// Must be called from within a tokio runtime.
+    pub fn start_health_check(&self) {
+        let endpoints = Arc::downgrade(&self.endpoints);
+        let interval = self.health_check_interval;
+        let request_timeout = self.request_timeout;
+        let cooldown = self.cooldown;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let endpoints = match Weak::upgrade(&endpoints) {
+                    Some(x) => x,
+                    None => return,
+                };
+                check_health(&endpoints, request_timeout, cooldown).await;
+            }
+        });
+    }
+}
+
+async fn check_health(endpoints: &[Endpoint], request_timeout: Duration, cooldown: Duration) {
+    for endpoint in endpoints.iter() {
+        let res = tokio::time::timeout(
+            request_timeout,
+            endpoint.client.request::<_, Value>("eth_blockNumber", ()),
+        )
+        .await;
+
+        match res {
+            Ok(Ok(_)) => {
+                if !endpoint.is_healthy() {
+                    info!(url = %endpoint.url, "eth rpc endpoint recovered");
+                }
+                endpoint.mark_healthy();
+            }
+            Ok(Err(e)) => {
+                warn!(url = %endpoint.url, "eth rpc health check failed, error: {:?}", e);
+                endpoint.mark_unhealthy(cooldown);
+            }
+            Err(_) => {
+                warn!(url = %endpoint.url, "eth rpc health check timed out");
+                endpoint.mark_unhealthy(cooldown);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for FailoverClient {
+    type Error = FailoverError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let mut answers = self.request_distinct(method, params, 1).await?;
+        Ok(answers.pop().expect("one answer").1)
+    }
+}
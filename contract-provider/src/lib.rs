@@ -1,72 +1,174 @@
 #[macro_use]
 extern crate tracing;
 
+mod failover;
+mod registry;
+
 use std::{
     collections::{HashMap, HashSet},
     str::FromStr,
-    sync::Arc,
-    time::Duration,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Result};
 use ethers::{
+    abi::Detokenize,
     prelude::abigen,
-    providers::{Http, HttpRateLimitRetryPolicy, Provider, RetryClient, RetryClientBuilder},
-    types::{H160, U256},
+    providers::Provider,
+    types::{BlockId, BlockNumber, Bytes, H160, U256},
 };
 
+pub use failover::{EthRpcConfig, FailoverClient, FailoverError};
+pub use registry::{MemoryRegistry, RegistrySnapshot, SignerRegistry, SignerSnapshot};
+
 pub const DA_SIGNER_ADDRESS: &str = "0x0000000000000000000000000000000000001000";
-pub const MIN_REQUIRED_SLICE: usize = 1024;
+/// Rows carrying the original blob, the only layout the encoder and the
+/// recovery support.
+pub const SYSTEMATIC_ROWS: usize = 1024;
+/// Ratio between encoded rows and the original blob rows.
+pub const ENCODING_REDUNDANCY: usize = 3;
 
 abigen!(DASigners, "./contract-provider/abi/IDASigners.json");
 
+/// Erasure coding layout derived from `DASigners.params()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodingParams {
+    /// Number of encoded rows, i.e. the length of a quorum.
+    pub encoded_slices: usize,
+    /// Number of rows carrying the original blob.
+    pub systematic_rows: usize,
+}
+
+impl EncodingParams {
+    /// Rejects layouts that blobs cannot be recovered from, i.e. any but
+    /// [`SYSTEMATIC_ROWS`] rows encoded into [`ENCODING_REDUNDANCY`] times as
+    /// many.
+    pub fn from_encoded_slices(encoded_slices: usize) -> Result<Self> {
+        if encoded_slices != SYSTEMATIC_ROWS * ENCODING_REDUNDANCY {
+            bail!(anyhow!(
+                "unsupported encoded slices {}, only {} systematic rows encoded into {} are supported",
+                encoded_slices,
+                SYSTEMATIC_ROWS,
+                SYSTEMATIC_ROWS * ENCODING_REDUNDANCY
+            ));
+        }
+
+        Ok(Self {
+            encoded_slices,
+            systematic_rows: SYSTEMATIC_ROWS,
+        })
+    }
+
+    /// Minimum number of distinct rows to recover a blob.
+    pub fn required_rows(&self) -> usize {
+        self.systematic_rows
+    }
+}
+
+pub struct QuorumSigners {
+    pub signers: HashMap<H160, SignerDetail>,
+    /// Systematic rows, which carry the original blob, owned by each signer.
+    pub systematic_rows: HashMap<H160, Vec<u32>>,
+    /// Parity rows owned by each signer.
+    pub parity_rows: HashMap<H160, Vec<u32>>,
+    pub params: EncodingParams,
+}
+
+impl QuorumSigners {
+    /// Splits the rows of `quorum`, listed by row index, into systematic and
+    /// parity rows of each signer.
+    pub fn new(quorum: Vec<H160>, params: EncodingParams, signers: Vec<SignerDetail>) -> Self {
+        let mut systematic_rows: HashMap<H160, Vec<u32>> = HashMap::new();
+        let mut parity_rows: HashMap<H160, Vec<u32>> = HashMap::new();
+        quorum.into_iter().enumerate().for_each(|(i, addr)| {
+            if i >= params.systematic_rows {
+                parity_rows
+                    .entry(addr)
+                    .and_modify(|e| e.push(i as u32))
+                    .or_insert(vec![i as u32]);
+            } else {
+                systematic_rows
+                    .entry(addr)
+                    .and_modify(|e| e.push(i as u32))
+                    .or_insert(vec![i as u32]);
+            }
+        });
+
+        let signers = signers
+            .into_iter()
+            .map(|t| (t.signer, t))
+            .collect::<HashMap<_, _>>();
+
+        Self {
+            signers,
+            systematic_rows,
+            parity_rows,
+            params,
+        }
+    }
+}
+
 pub struct ContractProvider {
-    #[allow(dead_code)]
-    provider: Arc<Provider<RetryClient<Http>>>,
+    provider: Arc<Provider<FailoverClient>>,
+
+    da_signers: Arc<DASigners<Provider<FailoverClient>>>,
+    cross_check_quorum: bool,
 
-    da_signers: Arc<DASigners<Provider<RetryClient<Http>>>>,
+    params: RwLock<Option<(Instant, EncodingParams)>>,
+    params_cache_ttl: Duration,
 }
 
 impl ContractProvider {
-    pub async fn new(eth_rpc_url: &str) -> Result<Self> {
-        let provider = Arc::new(Provider::new(
-            RetryClientBuilder::default()
-                .rate_limit_retries(100)
-                .timeout_retries(100)
-                .initial_backoff(Duration::from_millis(500))
-                .build(
-                    Http::from_str(eth_rpc_url)?,
-                    Box::new(HttpRateLimitRetryPolicy),
-                ),
-        ));
-
-        let da_signers = Arc::new(DASigners::new(
-            H160::from_str(DA_SIGNER_ADDRESS).unwrap(),
-            provider.clone(),
-        ));
+    pub async fn new(config: &EthRpcConfig) -> Result<Self> {
+        if config.cross_check_quorum && config.urls.len() < 2 {
+            bail!(anyhow!(
+                "cross checking quorums requires two eth rpc endpoints"
+            ));
+        }
+
+        let client = FailoverClient::new(config)?;
+        client.start_health_check();
+        let provider = Arc::new(Provider::new(client));
+
+        let address = H160::from_str(DA_SIGNER_ADDRESS).unwrap();
+        let da_signers = Arc::new(DASigners::new(address, provider.clone()));
 
         Ok(Self {
             provider,
             da_signers,
+            cross_check_quorum: config.cross_check_quorum,
+            params: RwLock::new(None),
+            params_cache_ttl: config.params_cache_ttl,
         })
     }
 
-    pub async fn get_signers(
-        &self,
-        epoch: u64,
-        quorum_id: u64,
-    ) -> Result<(
-        HashMap<H160, SignerDetail>,
-        HashMap<H160, Vec<u32>>,
-        HashMap<H160, Vec<u32>>,
-    )> {
+    /// Returns the erasure coding params, served from cache unless stale.
+    pub async fn get_encoding_params(&self) -> Result<EncodingParams> {
+        if let Some((fetched_at, params)) = *self.params.read().unwrap() {
+            if fetched_at.elapsed() < self.params_cache_ttl {
+                return Ok(params);
+            }
+        }
+
+        self.refresh_encoding_params().await
+    }
+
+    async fn refresh_encoding_params(&self) -> Result<EncodingParams> {
+        let raw = self.da_signers.params().call().await?;
+        let encoded_slices = usize::try_from(raw.encoded_slices)
+            .map_err(|_| anyhow!("encoded slices {} out of range", raw.encoded_slices))?;
+        let params = EncodingParams::from_encoded_slices(encoded_slices)?;
+        debug!("encoding params {:?}", params);
+
+        *self.params.write().unwrap() = Some((Instant::now(), params));
+        Ok(params)
+    }
+
+    pub async fn get_signers(&self, epoch: u64, quorum_id: u64) -> Result<QuorumSigners> {
         debug!("get signers fro epoch {}, quorum id {}", epoch, quorum_id);
 
-        let quorums = self
-            .da_signers
-            .get_quorum(U256::from(epoch), U256::from(quorum_id))
-            .call()
-            .await?;
+        let quorums = self.get_quorum(epoch, quorum_id).await?;
 
         if quorums.len() == 0 {
             bail!(anyhow!("quorum is empty"));
@@ -74,43 +176,116 @@ impl ContractProvider {
 
         debug!("quorum size {}", quorums.len());
 
-        let mut signer_first_1024_slices: HashMap<H160, Vec<u32>> = HashMap::new();
-        let mut signer_slices: HashMap<H160, Vec<u32>> = HashMap::new();
-        let mut unique_signer = HashSet::new();
-        quorums.into_iter().enumerate().for_each(|(i, addr)| {
-            if i >= MIN_REQUIRED_SLICE {
-                signer_slices
-                    .entry(addr)
-                    .and_modify(|e| e.push(i as u32))
-                    .or_insert(vec![i as u32]);
-            } else {
-                signer_first_1024_slices
-                    .entry(addr)
-                    .and_modify(|e| e.push(i as u32))
-                    .or_insert(vec![i as u32]);
+        let mut params = self.get_encoding_params().await?;
+        if quorums.len() != params.encoded_slices {
+            // params may have changed on chain since they were cached
+            params = self.refresh_encoding_params().await?;
+            if quorums.len() != params.encoded_slices {
+                bail!(anyhow!(
+                    "quorum size {} mismatches encoded slices {}",
+                    quorums.len(),
+                    params.encoded_slices
+                ));
             }
+        }
 
-            unique_signer.insert(addr);
-        });
-
+        let unique_signer = quorums.iter().copied().collect::<HashSet<_>>();
         let signers = self
-            .da_signers
-            .get_signer(unique_signer.into_iter().collect())
-            .call()
+            .get_signer_details(unique_signer.into_iter().collect())
             .await?;
 
         debug!("signer size {}", signers.len());
-        let signers = signers
-            .into_iter()
-            .map(|t| (t.signer, t))
-            .collect::<HashMap<_, _>>();
+        Ok(QuorumSigners::new(quorums, params, signers))
+    }
 
-        Ok((signers, signer_first_1024_slices, signer_slices))
+    pub async fn get_signer_details(&self, addresses: Vec<H160>) -> Result<Vec<SignerDetail>> {
+        Ok(self.da_signers.get_signer(addresses).call().await?)
+    }
+
+    async fn get_quorum(&self, epoch: u64, quorum_id: u64) -> Result<Vec<H160>> {
+        let call = self
+            .da_signers
+            .get_quorum(U256::from(epoch), U256::from(quorum_id));
+        if !self.cross_check_quorum {
+            return Ok(call.call().await?);
+        }
+
+        // the first two endpoints answering, each failed over to as usual
+        let block = BlockId::from(BlockNumber::Latest);
+        let answers = self
+            .provider
+            .as_ref()
+            .request_distinct::<_, Bytes>("eth_call", (&call.tx, block), 2)
+            .await?;
+        let mut quorums = vec![];
+        for (url, data) in answers.iter() {
+            let tokens = call
+                .function
+                .decode_output(data)
+                .map_err(|e| anyhow!("invalid quorum from eth rpc endpoint {}: {}", url, e))?;
+            quorums.push(Vec::<H160>::from_tokens(tokens)?);
+        }
+        if quorums[0] != quorums[1] {
+            bail!(anyhow!(
+                "quorum mismatch between eth rpc endpoints {} and {}",
+                answers[0].0,
+                answers[1].0
+            ));
+        }
+
+        Ok(quorums.swap_remove(0))
+    }
+
+    pub async fn get_signer(&self, address: H160) -> Result<SignerDetail> {
+        debug!("get signer {:?}", address);
+
+        if !self.da_signers.is_signer(address).call().await? {
+            bail!(anyhow!("{:?} is not a registered signer", address));
+        }
+
+        self.da_signers
+            .get_signer(vec![address])
+            .call()
+            .await?
+            .pop()
+            .ok_or(anyhow!("signer {:?} not returned", address))
+    }
+
+    pub async fn current_epoch(&self) -> Result<u64> {
+        let epoch = self.da_signers.epoch_number().call().await?;
+        u64::try_from(epoch).map_err(|_| anyhow!("epoch number {} out of range", epoch))
+    }
+
+    pub async fn get_quorum_count(&self, epoch: u64) -> Result<u64> {
+        debug!("get quorum count for epoch {}", epoch);
+
+        let count = self
+            .da_signers
+            .quorum_count(U256::from(epoch))
+            .call()
+            .await?;
+
+        u64::try_from(count).map_err(|_| anyhow!("quorum count {} out of range", count))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_params_from_encoded_slices() {
+        let params = EncodingParams::from_encoded_slices(3072).unwrap();
+        assert_eq!(params.systematic_rows, 1024);
+        assert_eq!(params.required_rows(), 1024);
+
+        // layouts the recovery cannot decode
+        assert!(EncodingParams::from_encoded_slices(0).is_err());
+        assert!(EncodingParams::from_encoded_slices(3071).is_err());
+        assert!(EncodingParams::from_encoded_slices(4096).is_err());
+        assert!(EncodingParams::from_encoded_slices(6144).is_err());
+    }
+
     #[test]
     fn it_works() {
         ethers::contract::Abigen::new("api", "./abi/IDASigners.json")
@@ -0,0 +1,272 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use ethers::types::{H160, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::{ContractProvider, EncodingParams, G1Point, G2Point, QuorumSigners, SignerDetail};
+
+/// Source of the quorums and signers of each epoch, the `DASigners` contract
+/// on chain or a snapshot of it.
+#[async_trait]
+pub trait SignerRegistry: Send + Sync {
+    /// Signers of a quorum and the rows they own.
+    async fn get_signers(&self, epoch: u64, quorum_id: u64) -> Result<QuorumSigners>;
+
+    async fn get_quorum_count(&self, epoch: u64) -> Result<u64>;
+
+    /// Erasure coding layout, shared by every quorum.
+    async fn get_encoding_params(&self) -> Result<EncodingParams>;
+
+    /// Socket and public keys of a registered signer.
+    async fn get_signer(&self, address: H160) -> Result<SignerDetail>;
+
+    async fn current_epoch(&self) -> Result<u64>;
+}
+
+#[async_trait]
+impl SignerRegistry for ContractProvider {
+    async fn get_signers(&self, epoch: u64, quorum_id: u64) -> Result<QuorumSigners> {
+        ContractProvider::get_signers(self, epoch, quorum_id).await
+    }
+
+    async fn get_quorum_count(&self, epoch: u64) -> Result<u64> {
+        ContractProvider::get_quorum_count(self, epoch).await
+    }
+
+    async fn get_encoding_params(&self) -> Result<EncodingParams> {
+        ContractProvider::get_encoding_params(self).await
+    }
+
+    async fn get_signer(&self, address: H160) -> Result<SignerDetail> {
+        ContractProvider::get_signer(self, address).await
+    }
+
+    async fn current_epoch(&self) -> Result<u64> {
+        ContractProvider::current_epoch(self).await
+    }
+}
+
+impl ContractProvider {
+    /// Reads the quorums of `epochs` and their signers from chain.
+    pub async fn export_snapshot(&self, epochs: RangeInclusive<u64>) -> Result<RegistrySnapshot> {
+        let current_epoch = self.current_epoch().await?;
+        let params = self.get_encoding_params().await?;
+
+        let mut quorums = BTreeMap::new();
+        let mut addresses = HashSet::new();
+        for epoch in epochs {
+            let quorum_count = self.get_quorum_count(epoch).await?;
+            let mut epoch_quorums = vec![];
+            for quorum_id in 0..quorum_count {
+                let quorum = self.get_quorum(epoch, quorum_id).await?;
+                addresses.extend(quorum.iter().copied());
+                epoch_quorums.push(quorum);
+            }
+            quorums.insert(epoch, epoch_quorums);
+        }
+
+        let mut addresses: Vec<H160> = addresses.into_iter().collect();
+        addresses.sort();
+        let signers = self
+            .get_signer_details(addresses)
+            .await?
+            .iter()
+            .map(SignerSnapshot::from)
+            .collect();
+
+        Ok(RegistrySnapshot {
+            current_epoch,
+            encoded_slices: params.encoded_slices,
+            quorums,
+            signers,
+        })
+    }
+}
+
+/// Registry content, as exported to and loaded from JSON.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistrySnapshot {
+    pub current_epoch: u64,
+    pub encoded_slices: usize,
+    /// Quorums of each epoch by quorum id, each listing the signer of every
+    /// row.
+    pub quorums: BTreeMap<u64, Vec<Vec<H160>>>,
+    pub signers: Vec<SignerSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignerSnapshot {
+    pub address: H160,
+    pub socket: String,
+    /// `[x, y]`
+    pub pk_g1: [U256; 2],
+    /// `[x, y]`
+    pub pk_g2: [[U256; 2]; 2],
+}
+
+impl From<&SignerDetail> for SignerSnapshot {
+    fn from(detail: &SignerDetail) -> Self {
+        Self {
+            address: detail.signer,
+            socket: detail.socket.clone(),
+            pk_g1: [detail.pk_g1.x, detail.pk_g1.y],
+            pk_g2: [detail.pk_g2.x, detail.pk_g2.y],
+        }
+    }
+}
+
+impl From<&SignerSnapshot> for SignerDetail {
+    fn from(snapshot: &SignerSnapshot) -> Self {
+        Self {
+            signer: snapshot.address,
+            socket: snapshot.socket.clone(),
+            pk_g1: G1Point {
+                x: snapshot.pk_g1[0],
+                y: snapshot.pk_g1[1],
+            },
+            pk_g2: G2Point {
+                x: snapshot.pk_g2[0],
+                y: snapshot.pk_g2[1],
+            },
+        }
+    }
+}
+
+/// Registry held in memory, for tests and deployments without chain access.
+pub struct MemoryRegistry {
+    current_epoch: u64,
+    params: EncodingParams,
+    quorums: BTreeMap<u64, Vec<Vec<H160>>>,
+    signers: HashMap<H160, SignerDetail>,
+}
+
+impl MemoryRegistry {
+    pub fn new(snapshot: RegistrySnapshot) -> Result<Self> {
+        let params = EncodingParams::from_encoded_slices(snapshot.encoded_slices)?;
+        for (epoch, quorums) in snapshot.quorums.iter() {
+            for (quorum_id, quorum) in quorums.iter().enumerate() {
+                if quorum.len() != params.encoded_slices {
+                    bail!(anyhow!(
+                        "quorum {} of epoch {} has {} rows, expect {}",
+                        quorum_id,
+                        epoch,
+                        quorum.len(),
+                        params.encoded_slices
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            current_epoch: snapshot.current_epoch,
+            params,
+            quorums: snapshot.quorums,
+            signers: snapshot
+                .signers
+                .iter()
+                .map(|s| (s.address, SignerDetail::from(s)))
+                .collect(),
+        })
+    }
+
+    /// Loads a snapshot exported to JSON.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read(path)
+            .map_err(|e| anyhow!("failed to read registry snapshot {:?}: {}", path, e))?;
+        let snapshot = serde_json::from_slice(&content)
+            .map_err(|e| anyhow!("invalid registry snapshot {:?}: {}", path, e))?;
+        Self::new(snapshot)
+    }
+
+    fn get_quorum(&self, epoch: u64, quorum_id: u64) -> Result<&Vec<H160>> {
+        self.quorums
+            .get(&epoch)
+            .and_then(|quorums| quorums.get(quorum_id as usize))
+            .ok_or(anyhow!(
+                "quorum {} of epoch {} not in registry",
+                quorum_id,
+                epoch
+            ))
+    }
+}
+
+#[async_trait]
+impl SignerRegistry for MemoryRegistry {
+    async fn get_signers(&self, epoch: u64, quorum_id: u64) -> Result<QuorumSigners> {
+        let quorum = self.get_quorum(epoch, quorum_id)?;
+
+        // like on chain, unknown signers are left out
+        let unique_signer = quorum.iter().collect::<HashSet<_>>();
+        let signers = unique_signer
+            .into_iter()
+            .filter_map(|address| self.signers.get(address).cloned())
+            .collect();
+
+        Ok(QuorumSigners::new(quorum.clone(), self.params, signers))
+    }
+
+    async fn get_quorum_count(&self, epoch: u64) -> Result<u64> {
+        Ok(self.quorums.get(&epoch).map_or(0, |quorums| quorums.len()) as u64)
+    }
+
+    async fn get_encoding_params(&self) -> Result<EncodingParams> {
+        Ok(self.params)
+    }
+
+    async fn get_signer(&self, address: H160) -> Result<SignerDetail> {
+        self.signers
+            .get(&address)
+            .cloned()
+            .ok_or(anyhow!("{:?} is not a registered signer", address))
+    }
+
+    async fn current_epoch(&self) -> Result<u64> {
+        Ok(self.current_epoch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_registry_splits_rows() {
+        let (a, b) = (H160::repeat_byte(1), H160::repeat_byte(2));
+        let mut quorum = vec![a; 3072];
+        quorum[1] = b;
+        quorum[1024] = b;
+        let snapshot = RegistrySnapshot {
+            current_epoch: 5,
+            encoded_slices: 3072,
+            quorums: BTreeMap::from([(5, vec![quorum])]),
+            signers: vec![SignerSnapshot {
+                address: a,
+                socket: "127.0.0.1:34010".into(),
+                pk_g1: Default::default(),
+                pk_g2: Default::default(),
+            }],
+        };
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let registry = MemoryRegistry::new(serde_json::from_str(&json).unwrap()).unwrap();
+
+        let signers = registry.get_signers(5, 0).await.unwrap();
+        assert_eq!(signers.systematic_rows[&a].len(), 1023);
+        assert_eq!(signers.systematic_rows[&b], vec![1]);
+        assert_eq!(signers.parity_rows[&a].len(), 2047);
+        assert_eq!(signers.parity_rows[&b], vec![1024]);
+        // b is not registered
+        assert_eq!(signers.signers.len(), 1);
+
+        assert_eq!(registry.get_quorum_count(5).await.unwrap(), 1);
+        assert_eq!(registry.get_quorum_count(6).await.unwrap(), 0);
+        assert!(registry.get_signers(5, 1).await.is_err());
+    }
+}
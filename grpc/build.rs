@@ -1,7 +1,12 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:rerun-if-changed=proto");
 
-    tonic_build::compile_protos("proto/retriever.proto")?;
+    tonic_build::configure()
+        .protoc_arg("--experimental_allow_proto3_optional") // for older systems
+        .compile(&["proto/retriever.proto"], &["proto"])?;
+
+    // the server is only used by the tests, as a stand-in disperser
+    tonic_build::configure().compile(&["proto/disperser.proto"], &["proto"])?;
 
     Ok(())
 }
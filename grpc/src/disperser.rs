@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use tonic::transport::{Channel, Endpoint};
+
+use self::proto::{disperser_client::DisperserClient, RetrieveBlobRequest};
+
+pub mod proto {
+    tonic::include_proto!("disperser");
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisperserConfig {
+    pub endpoint: String,
+    pub timeout: Duration,
+    pub max_message_size: usize,
+}
+
+/// Client of the disperser the blobs were dispersed with, which serves them
+/// without the signer fan-out but has to be checked against the storage root.
+#[derive(Clone)]
+pub struct Disperser {
+    client: DisperserClient<Channel>,
+}
+
+impl Disperser {
+    /// Connects on first use.
+    pub fn new(config: &DisperserConfig) -> Result<Self> {
+        let channel = Endpoint::from_shared(config.endpoint.clone())?
+            .connect_timeout(config.timeout)
+            .timeout(config.timeout)
+            .connect_lazy();
+
+        Ok(Self {
+            client: DisperserClient::new(channel)
+                .max_decoding_message_size(config.max_message_size)
+                .max_encoding_message_size(config.max_message_size),
+        })
+    }
+
+    pub async fn retrieve_blob(
+        &self,
+        storage_root: Vec<u8>,
+        epoch: u64,
+        quorum_id: u64,
+    ) -> Result<Vec<u8>> {
+        let reply = self
+            .client
+            .clone()
+            .retrieve_blob(RetrieveBlobRequest {
+                storage_root,
+                epoch,
+                quorum_id,
+            })
+            .await?;
+        Ok(reply.into_inner().data)
+    }
+}
@@ -0,0 +1,210 @@
+//! Byte layout of the systematic rows of an encoded blob.
+//!
+//! The encoder lays a blob out as rows of `ROW_COLUMNS` field elements, each
+//! element carrying `RAW_UNIT` bytes of the blob, so systematic row `i` holds
+//! blob bytes `[i * ROW_BYTES, (i + 1) * ROW_BYTES)`.
+//!
+//! An encoded slice starts with its row index (`u64`, little endian), followed
+//! by the row elements as a `u64` length prefixed sequence of 32-byte little
+//! endian scalars. The proofs trailing the row are not needed to read data.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use anyhow::{bail, Result};
+
+pub const ROW_COLUMNS: usize = 1024;
+pub const RAW_UNIT: usize = 31;
+pub const ROW_BYTES: usize = ROW_COLUMNS * RAW_UNIT;
+
+const SCALAR_SIZE: usize = 32;
+
+/// Returns the systematic rows covering blob bytes `[offset, offset + length)`,
+/// `None` if the range ends past the last of `systematic_rows` rows.
+pub fn rows_for_range(offset: u64, length: u64, systematic_rows: usize) -> Option<Range<u32>> {
+    let start = offset / ROW_BYTES as u64;
+    let end = offset.checked_add(length)?.div_ceil(ROW_BYTES as u64);
+    // bounded by the row count before narrowing, so no row index wraps
+    if end > systematic_rows as u64 {
+        return None;
+    }
+    Some(u32::try_from(start).ok()?..u32::try_from(end).ok()?)
+}
+
+/// Checks that `slice` is the one of row `row_index`, as rows of any kind
+/// start with their index.
+pub fn check_row_index(row_index: u32, slice: &[u8]) -> Result<()> {
+    let (index, _) = read_u64(slice)?;
+    if index != row_index as u64 {
+        bail!("row index mismatch, expect {}, got {}", row_index, index);
+    }
+    Ok(())
+}
+
+/// Extracts the blob bytes carried by systematic row `row_index`.
+pub fn decode_systematic_row(row_index: u32, slice: &[u8]) -> Result<Vec<u8>> {
+    let (index, rest) = read_u64(slice)?;
+    if index != row_index as u64 {
+        bail!("row index mismatch, expect {}, got {}", row_index, index);
+    }
+
+    let (columns, rest) = read_u64(rest)?;
+    if columns != ROW_COLUMNS as u64 {
+        bail!(
+            "row {} has {} columns, expect {}",
+            row_index,
+            columns,
+            ROW_COLUMNS
+        );
+    }
+
+    if rest.len() < ROW_COLUMNS * SCALAR_SIZE {
+        bail!("row {} is truncated", row_index);
+    }
+
+    let mut data = Vec::with_capacity(ROW_BYTES);
+    for scalar in rest[..ROW_COLUMNS * SCALAR_SIZE].chunks_exact(SCALAR_SIZE) {
+        // raw units never reach the top byte of a scalar
+        if scalar[RAW_UNIT] != 0 {
+            bail!("row {} carries a non raw scalar", row_index);
+        }
+        data.extend_from_slice(&scalar[..RAW_UNIT]);
+    }
+
+    Ok(data)
+}
+
+/// Assembles a blob by concatenating all its systematic rows, which avoids
+/// erasure decoding when every systematic row is available. The zero padding
+/// of the last rows is kept, as the rows do not record the blob length.
+pub fn assemble_systematic_rows(
+    slices: &BTreeMap<usize, Vec<u8>>,
+    systematic_rows: usize,
+) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(systematic_rows * ROW_BYTES);
+    for row in 0..systematic_rows {
+        let slice = match slices.get(&row) {
+            Some(slice) => slice,
+            None => bail!("systematic row {} is missing", row),
+        };
+        data.extend_from_slice(&decode_systematic_row(row as u32, slice)?);
+    }
+
+    Ok(data)
+}
+
+fn read_u64(buf: &[u8]) -> Result<(u64, &[u8])> {
+    if buf.len() < 8 {
+        bail!("slice is truncated");
+    }
+    let (head, rest) = buf.split_at(8);
+    Ok((u64::from_le_bytes(head.try_into().unwrap()), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_row(index: u32, data: &[u8]) -> Vec<u8> {
+        let mut slice = vec![];
+        slice.extend_from_slice(&(index as u64).to_le_bytes());
+        slice.extend_from_slice(&(ROW_COLUMNS as u64).to_le_bytes());
+        for unit in data.chunks(RAW_UNIT) {
+            let mut scalar = [0u8; SCALAR_SIZE];
+            scalar[..unit.len()].copy_from_slice(unit);
+            slice.extend_from_slice(&scalar);
+        }
+        slice
+    }
+
+    #[test]
+    fn rows_for_range_covers_boundaries() {
+        assert_eq!(rows_for_range(0, 1, 4), Some(0..1));
+        assert_eq!(rows_for_range(0, ROW_BYTES as u64, 4), Some(0..1));
+        assert_eq!(rows_for_range(ROW_BYTES as u64 - 1, 2, 4), Some(0..2));
+        assert_eq!(rows_for_range(ROW_BYTES as u64 * 3, 10, 4), Some(3..4));
+        assert_eq!(
+            rows_for_range(ROW_BYTES as u64 * 3, ROW_BYTES as u64 + 1, 4),
+            None
+        );
+    }
+
+    #[test]
+    fn rows_for_range_rejects_rows_past_u32() {
+        let row_bytes = ROW_BYTES as u64;
+        // would wrap to rows 0..0 if narrowed before the bound check
+        assert_eq!(rows_for_range(0, 31744 << 32, 1024), None);
+        assert_eq!(
+            rows_for_range(0, (u32::MAX as u64 + 1) * row_bytes, 1024),
+            None
+        );
+        assert_eq!(
+            rows_for_range((u32::MAX as u64 + 1) * row_bytes, 1, 1024),
+            None
+        );
+        assert_eq!(rows_for_range(u64::MAX - 1, 1, 1024), None);
+        assert_eq!(rows_for_range(u64::MAX, 1, 1024), None);
+    }
+
+    #[test]
+    fn decode_systematic_row_roundtrip() {
+        let data: Vec<u8> = (0..ROW_BYTES).map(|i| i as u8).collect();
+        let slice = encode_row(7, &data);
+
+        assert_eq!(decode_systematic_row(7, &slice).unwrap(), data);
+        assert!(decode_systematic_row(8, &slice).is_err());
+        assert!(decode_systematic_row(7, &slice[..slice.len() - 1]).is_err());
+        assert!(check_row_index(7, &slice).is_ok());
+        assert!(check_row_index(8, &slice).is_err());
+        assert!(check_row_index(7, &slice[..7]).is_err());
+    }
+
+    #[test]
+    fn assemble_systematic_rows_requires_every_row() {
+        let mut slices = BTreeMap::new();
+        slices.insert(0, encode_row(0, &[1u8; ROW_BYTES]));
+        slices.insert(1, encode_row(1, &[2u8; ROW_BYTES]));
+
+        let data = assemble_systematic_rows(&slices, 2).unwrap();
+        assert_eq!(data.len(), 2 * ROW_BYTES);
+        assert_eq!(data[ROW_BYTES - 1], 1);
+        assert_eq!(data[ROW_BYTES], 2);
+
+        assert!(assemble_systematic_rows(&slices, 3).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs the encoder params, set ZG_ENCODER_PARAMS_DIR"]
+    fn matches_the_encoder() {
+        use mock_signer::encoder::Encoder;
+        use zg_da_recovery::recover_from_da_slice;
+
+        use crate::storage;
+
+        let params_dir = std::env::var("ZG_ENCODER_PARAMS_DIR").unwrap();
+        let mut blob: Vec<u8> = (0..3 * ROW_BYTES + 5)
+            .map(|i| (i % 251) as u8 + 1)
+            .collect();
+        blob.resize(blob.len() + 1000, 0);
+        let encoded = Encoder::new(&params_dir).encode(&blob).unwrap();
+        assert_eq!(encoded.storage_root, storage::file_root(&blob).0);
+
+        let slices: BTreeMap<usize, Vec<u8>> = encoded.rows.into_iter().enumerate().collect();
+        let data = assemble_systematic_rows(&slices, ROW_COLUMNS).unwrap();
+        assert_eq!(data.len(), ROW_COLUMNS * ROW_BYTES);
+        assert_eq!(data[..blob.len()], blob[..]);
+        assert!(data[blob.len()..].iter().all(|b| *b == 0));
+
+        let systematic: BTreeMap<usize, Vec<u8>> = slices
+            .range(..ROW_COLUMNS)
+            .map(|(row, slice)| (*row, slice.clone()))
+            .collect();
+        assert_eq!(recover_from_da_slice(&systematic).unwrap(), data);
+
+        // a systematic row is missing, so the blob comes from parity rows
+        let parity: BTreeMap<usize, Vec<u8>> = slices
+            .range(ROW_COLUMNS..2 * ROW_COLUMNS)
+            .map(|(row, slice)| (*row, slice.clone()))
+            .collect();
+        assert_eq!(recover_from_da_slice(&parity).unwrap(), data);
+    }
+}
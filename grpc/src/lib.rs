@@ -1,42 +1,48 @@
 #[macro_use]
 extern crate tracing;
 
+mod disperser;
+mod layout;
+mod metrics;
+mod rate_limit;
+mod retrieval;
 mod service;
+mod storage;
 
-pub use service::retriever;
+pub use disperser::{proto as disperser_proto, Disperser, DisperserConfig};
+pub use metrics::run_metrics_server;
+pub use retrieval::{Blob, BlobSource, RetrieveError, Retriever, RetrieverBuilder};
+pub use service::{retriever, RetrieverService, ServiceConfig};
+pub use storage::{file_root, StorageNodeConfig, StorageNodes};
 
 use crate::service::retriever::retriever_server::RetrieverServer;
-use contract_provider::ContractProvider;
-use service::RetrieverService;
-use signer_provider::SignerProvider;
-use std::{net::SocketAddr, sync::Arc};
-use task_executor::TaskExecutor;
-use tonic::transport::Server;
-
-const MESSAGE_SIZE_LIMIT: usize = 1024 * 1024 * 1024; // 1G
+use std::{
+    future::Future,
+    sync::{atomic::AtomicU64, Arc},
+};
+use tokio::sync::RwLock;
+use tonic::transport::{server::TcpIncoming, Server};
 
 pub async fn run_server(
-    addr: SocketAddr,
-    contract_provider: ContractProvider,
-    signer_provider: Arc<SignerProvider>,
-    max_ongoing_sign_request: Option<u64>,
-    executor: TaskExecutor,
+    incoming: TcpIncoming,
+    retriever: Arc<Retriever>,
+    config: Arc<RwLock<ServiceConfig>>,
+    ongoing_requests: Arc<AtomicU64>,
+    shutdown: impl Future<Output = ()>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let signer_service = RetrieverService::new(
-        contract_provider,
-        signer_provider,
-        max_ongoing_sign_request,
-        executor,
-    );
+    let max_message_size = config.read().await.max_message_size;
+    let signer_service = RetrieverService::new(retriever, config, ongoing_requests);
 
-    info!("grpc server listening {:?}", addr);
+    info!("grpc server listening");
     Server::builder()
         .add_service(
             RetrieverServer::new(signer_service)
-                .max_decoding_message_size(MESSAGE_SIZE_LIMIT)
-                .max_encoding_message_size(MESSAGE_SIZE_LIMIT),
+                .max_decoding_message_size(max_message_size)
+                .max_encoding_message_size(max_message_size),
         )
-        .serve(addr)
+        // stops accepting calls once `shutdown` resolves, then waits for the
+        // ongoing ones to finish
+        .serve_with_incoming_shutdown(incoming, shutdown)
         .await?;
     Ok(())
 }
@@ -0,0 +1,44 @@
+use std::{convert::Infallible, net::TcpListener};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, Encoder, IntCounterVec, TextEncoder};
+
+lazy_static! {
+    /// Recovered blobs by path: `systematic` when the systematic rows were
+    /// concatenated, `erasure` when Reed-Solomon decoding was needed,
+    /// `disperser` when the checked disperser reply was used, `storage` when
+    /// the file was downloaded from storage nodes.
+    pub static ref BLOB_RECOVERY_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "retriever_blob_recovery_total",
+        "Number of recovered blobs by recovery path",
+        &["path"]
+    )
+    .unwrap();
+}
+
+pub async fn run_metrics_server(listener: TcpListener) -> Result<(), Box<dyn std::error::Error>> {
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(serve_metrics)) });
+
+    info!("metrics server listening {:?}", listener.local_addr()?);
+    Server::from_tcp(listener)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn serve_metrics(_request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!("failed to encode metrics, error: {:?}", e);
+    }
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(buffer))
+        .unwrap())
+}
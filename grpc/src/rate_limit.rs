@@ -0,0 +1,63 @@
+use std::time::Instant;
+
+/// Token bucket admitting `rate` requests per second on average, with bursts
+/// of up to `burst` requests.
+pub struct RateLimiter {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            // clamped to the burst on first use, i.e. the bucket starts full
+            tokens: f64::INFINITY,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token if available. Rate and burst are passed on every call so
+    /// that they can change at runtime.
+    pub fn try_acquire(&mut self, rate: u64, burst: u64) -> bool {
+        self.try_acquire_at(Instant::now(), rate, burst)
+    }
+
+    fn try_acquire_at(&mut self, now: Instant, rate: u64, burst: u64) -> bool {
+        let capacity = burst.max(1) as f64;
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate as f64).min(capacity);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn refills_at_rate_up_to_burst() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new();
+        limiter.last_refill = start;
+
+        // a long idle period only fills the bucket up to the burst
+        let now = start + Duration::from_secs(10);
+        assert!(limiter.try_acquire_at(now, 2, 3));
+        assert!(limiter.try_acquire_at(now, 2, 3));
+        assert!(limiter.try_acquire_at(now, 2, 3));
+        assert!(!limiter.try_acquire_at(now, 2, 3));
+
+        // half a second at 2 per second brings back one token
+        let now = now + Duration::from_millis(500);
+        assert!(limiter.try_acquire_at(now, 2, 3));
+        assert!(!limiter.try_acquire_at(now, 2, 3));
+    }
+}
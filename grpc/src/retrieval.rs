@@ -0,0 +1,932 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::bail;
+use bytes::Bytes;
+use contract_provider::{QuorumSigners, SignerDetail, SignerRegistry};
+use ethers::types::H160;
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+};
+use signer_provider::{RetrieveParam, SignerEndpoint, SliceError, SliceSource};
+use task_executor::TaskExecutor;
+use thiserror::Error;
+use tokio::sync::{Mutex, RwLock};
+use tracing::Instrument;
+use zg_da_recovery::recover_from_da_slice;
+
+use crate::{
+    disperser::Disperser,
+    layout::{self, ROW_BYTES},
+    metrics,
+    storage::{self, StorageNodes},
+};
+
+const STORAGE_ROOT_SIZE: usize = 32;
+
+/// Rows of one signer, with their slices.
+type SliceResult = anyhow::Result<(H160, Vec<u32>, Vec<Vec<u8>>)>;
+
+/// Slices of one signer, `None` if the task was cancelled.
+type SliceTask = BoxFuture<'static, Option<SliceResult>>;
+
+/// The blob whose rows are requested, and the signers serving them.
+struct RowRequest<'a> {
+    data_root: &'a [u8],
+    epoch: u64,
+    quorum_id: u64,
+    signers: &'a HashMap<H160, SignerDetail>,
+    // signers answering malformed slices, not asked again
+    invalid_signers: Arc<Mutex<HashSet<H160>>>,
+}
+
+/// Rows received for a blob, with the signers that served them.
+#[derive(Default)]
+struct ReceivedRows {
+    slices: BTreeMap<usize, Vec<u8>>,
+    served_by: BTreeMap<H160, Vec<usize>>,
+}
+
+impl ReceivedRows {
+    fn insert(&mut self, address: H160, indices: Vec<u32>, slices: Vec<Vec<u8>>) {
+        for (row, slice) in indices.into_iter().zip(slices) {
+            self.slices.insert(row as usize, slice);
+            self.served_by
+                .entry(address)
+                .or_default()
+                .push(row as usize);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.slices.len()
+    }
+
+    /// Number of rows not served by `signer`.
+    fn count_without(&self, signer: Option<H160>) -> usize {
+        let served = signer
+            .and_then(|signer| self.served_by.get(&signer))
+            .map_or(0, Vec::len);
+        self.slices.len() - served
+    }
+
+    /// Rows served by each signer but `excluded`, with their slices.
+    fn by_signer(&self, excluded: Option<H160>) -> Vec<(H160, Vec<u32>, Vec<Vec<u8>>)> {
+        self.served_by
+            .iter()
+            .filter(|(address, _)| Some(**address) != excluded)
+            .map(|(address, rows)| {
+                let slices = rows.iter().map(|row| self.slices[row].clone()).collect();
+                let rows = rows.iter().map(|row| *row as u32).collect();
+                (*address, rows, slices)
+            })
+            .collect()
+    }
+
+    /// Rows not served by `signer`.
+    fn without(&self, signer: H160) -> BTreeMap<usize, Vec<u8>> {
+        let served = self.served_by.get(&signer);
+        self.slices
+            .iter()
+            .filter(|(row, _)| !served.is_some_and(|served| served.contains(row)))
+            .map(|(row, slice)| (*row, slice.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RetrieveError {
+    #[error("storage root must be 32 bytes, got {0}")]
+    InvalidStorageRoot(usize),
+    #[error("epoch {epoch} is beyond the current epoch {current_epoch}")]
+    EpochNotReached { epoch: u64, current_epoch: u64 },
+    #[error("quorum {quorum_id} does not exist, epoch {epoch} has {quorum_count} quorums")]
+    UnknownQuorum {
+        epoch: u64,
+        quorum_id: u64,
+        quorum_count: u64,
+    },
+    #[error("failed to read the signer registry: {0:?}")]
+    Registry(anyhow::Error),
+    #[error("no quorum available in epoch {0}")]
+    NoQuorum(u64),
+    #[error("only {available} of {required} required rows available")]
+    InsufficientRows { available: usize, required: usize },
+    #[error("fail to recover slice: {0}")]
+    Recovery(String),
+    #[error("{0}")]
+    InvalidRange(String),
+    #[error("{0}")]
+    OutOfRange(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+/// Where a blob was served from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlobSource {
+    Signers,
+    Disperser,
+    StorageNode,
+}
+
+#[derive(Clone, Debug)]
+pub struct Blob {
+    pub data: Bytes,
+    /// Quorum the blob was retrieved from, unknown if a storage node served a
+    /// request for any quorum.
+    pub quorum_id: Option<u64>,
+    pub source: BlobSource,
+}
+
+pub struct RetrieverBuilder {
+    registry: Arc<dyn SignerRegistry>,
+    slice_source: Arc<dyn SliceSource>,
+    disperser: Option<Disperser>,
+    storage_nodes: Option<StorageNodes>,
+    denied_signers: HashSet<H160>,
+    executor: Option<TaskExecutor>,
+}
+
+impl RetrieverBuilder {
+    /// Asked for blobs first, checked against rows of the signers.
+    pub fn disperser(mut self, disperser: Disperser) -> Self {
+        self.disperser = Some(disperser);
+        self
+    }
+
+    /// Asked for blobs when the signers cannot serve them.
+    pub fn storage_nodes(mut self, storage_nodes: StorageNodes) -> Self {
+        self.storage_nodes = Some(storage_nodes);
+        self
+    }
+
+    pub fn denied_signers(mut self, signers: HashSet<H160>) -> Self {
+        self.denied_signers = signers;
+        self
+    }
+
+    /// Runs the slice requests on `executor`, on the current tokio runtime
+    /// otherwise.
+    pub fn executor(mut self, executor: TaskExecutor) -> Self {
+        self.executor = Some(executor);
+        self
+    }
+
+    pub fn build(self) -> Retriever {
+        Retriever {
+            registry: self.registry,
+            slice_source: self.slice_source,
+            disperser: self.disperser,
+            storage_nodes: self.storage_nodes,
+            denied_signers: RwLock::new(self.denied_signers),
+            executor: self.executor,
+        }
+    }
+}
+
+/// Retrieves blobs from the DA signers of their quorum and checks them, so
+/// the signers need not be trusted.
+pub struct Retriever {
+    registry: Arc<dyn SignerRegistry>,
+    slice_source: Arc<dyn SliceSource>,
+    disperser: Option<Disperser>,
+    storage_nodes: Option<StorageNodes>,
+    denied_signers: RwLock<HashSet<H160>>,
+    executor: Option<TaskExecutor>,
+}
+
+impl Retriever {
+    pub fn builder(
+        registry: Arc<dyn SignerRegistry>,
+        slice_source: Arc<dyn SliceSource>,
+    ) -> RetrieverBuilder {
+        RetrieverBuilder {
+            registry,
+            slice_source,
+            disperser: None,
+            storage_nodes: None,
+            denied_signers: HashSet::new(),
+            executor: None,
+        }
+    }
+
+    /// Signers never asked for slices, replacing the previous ones.
+    pub async fn deny_signers(&self, signers: HashSet<H160>) {
+        *self.denied_signers.write().await = signers;
+    }
+
+    /// Retrieves the blob from the quorum, or from every quorum of the epoch
+    /// in turn if `quorum_id` is `None`.
+    pub async fn retrieve(
+        &self,
+        storage_root: &[u8],
+        epoch: u64,
+        quorum_id: Option<u64>,
+    ) -> Result<Blob, RetrieveError> {
+        self.validate(storage_root, epoch, quorum_id).await?;
+
+        let res = match quorum_id {
+            Some(quorum_id) => {
+                self.retrieve_blob_quorum(storage_root.to_vec(), epoch, quorum_id)
+                    .await
+            }
+            None => {
+                self.retrieve_blob_from_any_quorum(storage_root.to_vec(), epoch)
+                    .await
+            }
+        };
+
+        match (res, self.storage_nodes.as_ref()) {
+            // only blobs the signers failed to serve, not invalid requests
+            (
+                Err(e @ (RetrieveError::InsufficientRows { .. } | RetrieveError::Recovery(_))),
+                Some(storage_nodes),
+            ) => {
+                self.retrieve_from_storage_nodes(storage_nodes, storage_root, quorum_id, e)
+                    .await
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// Retrieves `length` bytes of the blob from `offset`, from the signers
+    /// of the rows covering them only if they all answer. Such ranges are not
+    /// checked against the storage root, which commits to the whole blob.
+    pub async fn retrieve_range(
+        &self,
+        storage_root: &[u8],
+        epoch: u64,
+        quorum_id: u64,
+        offset: u64,
+        length: u64,
+    ) -> Result<Bytes, RetrieveError> {
+        self.validate(storage_root, epoch, Some(quorum_id)).await?;
+
+        self.retrieve_range_inner(storage_root.to_vec(), epoch, quorum_id, offset, length)
+            .await
+            .map(Bytes::from)
+    }
+
+    /// Rejects requests no quorum can serve, before any signer is asked.
+    async fn validate(
+        &self,
+        storage_root: &[u8],
+        epoch: u64,
+        quorum_id: Option<u64>,
+    ) -> Result<(), RetrieveError> {
+        if storage_root.len() != STORAGE_ROOT_SIZE {
+            return Err(RetrieveError::InvalidStorageRoot(storage_root.len()));
+        }
+
+        let current_epoch = self
+            .registry
+            .current_epoch()
+            .instrument(info_span!("chain_lookup", method = "epochNumber"))
+            .await
+            .map_err(RetrieveError::Registry)?;
+        if epoch > current_epoch {
+            return Err(RetrieveError::EpochNotReached {
+                epoch,
+                current_epoch,
+            });
+        }
+
+        // every quorum of the epoch is tried otherwise
+        if let Some(quorum_id) = quorum_id {
+            let quorum_count = self.get_quorum_count(epoch).await?;
+            if quorum_id >= quorum_count {
+                return Err(RetrieveError::UnknownQuorum {
+                    epoch,
+                    quorum_id,
+                    quorum_count,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_quorum_count(&self, epoch: u64) -> Result<u64, RetrieveError> {
+        self.registry
+            .get_quorum_count(epoch)
+            .instrument(info_span!("chain_lookup", method = "quorumCount", epoch))
+            .await
+            .map_err(RetrieveError::Registry)
+    }
+
+    /// Fetches the quorum from the registry, leaving out denied signers and
+    /// those without details.
+    async fn get_quorum(&self, epoch: u64, quorum_id: u64) -> Result<QuorumSigners, RetrieveError> {
+        let mut quorum = self
+            .registry
+            .get_signers(epoch, quorum_id)
+            .instrument(info_span!(
+                "chain_lookup",
+                method = "getQuorum",
+                epoch,
+                quorum_id
+            ))
+            .await
+            .map_err(RetrieveError::Registry)?;
+
+        let denied_signers = self.denied_signers.read().await;
+        let signers = &quorum.signers;
+        // rows of signers unknown to the registry cannot be fetched
+        let eligible = |address: &H160| {
+            if !signers.contains_key(address) {
+                warn!(signer = ?address, "signer details unavailable, skip its rows");
+                return false;
+            }
+            !denied_signers.contains(address)
+        };
+        quorum
+            .systematic_rows
+            .retain(|address, _| eligible(address));
+        quorum.parity_rows.retain(|address, _| eligible(address));
+
+        Ok(quorum)
+    }
+
+    async fn retrieve_blob_from_any_quorum(
+        &self,
+        data_root: Vec<u8>,
+        epoch: u64,
+    ) -> Result<Blob, RetrieveError> {
+        let quorum_count = self.get_quorum_count(epoch).await?;
+
+        let mut last_err = RetrieveError::NoQuorum(epoch);
+        for quorum_id in 0..quorum_count {
+            match self
+                .retrieve_blob_quorum(data_root.clone(), epoch, quorum_id)
+                .await
+            {
+                Ok(blob) => {
+                    info!(quorum_id, "blob served by quorum");
+                    return Ok(blob);
+                }
+                Err(e) => {
+                    warn!(quorum_id, "retrieve from quorum failed, error: {:?}", e);
+                    last_err = e;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+
+    /// Serves the blob from the disperser if one is configured and its reply
+    /// checks out, from the signers otherwise.
+    async fn retrieve_blob_quorum(
+        &self,
+        data_root: Vec<u8>,
+        epoch: u64,
+        quorum_id: u64,
+    ) -> Result<Blob, RetrieveError> {
+        if let Some(disperser) = self.disperser.as_ref() {
+            match self
+                .retrieve_from_disperser(disperser, &data_root, epoch, quorum_id)
+                .await
+            {
+                Ok(data) => {
+                    metrics::BLOB_RECOVERY_TOTAL
+                        .with_label_values(&["disperser"])
+                        .inc();
+                    info!("blob served by the disperser");
+                    return Ok(Blob {
+                        data: data.into(),
+                        quorum_id: Some(quorum_id),
+                        source: BlobSource::Disperser,
+                    });
+                }
+                Err(e) => {
+                    warn!(
+                        "disperser reply unusable, fall back to signers, error: {:?}",
+                        e
+                    );
+                }
+            }
+        }
+
+        let data = self
+            .retrieve_blob_inner(data_root, epoch, quorum_id)
+            .await?;
+        Ok(Blob {
+            data: data.into(),
+            quorum_id: Some(quorum_id),
+            source: BlobSource::Signers,
+        })
+    }
+
+    /// Last resort when the signers cannot serve a blob: its file in 0G
+    /// storage, checked against the storage root. `err` is the signer
+    /// failure, returned if the storage nodes fail as well.
+    async fn retrieve_from_storage_nodes(
+        &self,
+        storage_nodes: &StorageNodes,
+        data_root: &[u8],
+        quorum_id: Option<u64>,
+        err: RetrieveError,
+    ) -> Result<Blob, RetrieveError> {
+        warn!(
+            "signers cannot serve the blob, fall back to storage nodes, error: {:?}",
+            err
+        );
+        let download = async {
+            // no blob exceeds the quorum capacity, whatever the node claims
+            let params = self.registry.get_encoding_params().await?;
+            let data = storage_nodes
+                .download(data_root, params.systematic_rows * ROW_BYTES)
+                .await?;
+            // already truncated to the file size, the root is checked as is
+            storage::check_file_root(&data, data_root)?;
+            anyhow::Ok(data)
+        };
+        match download.instrument(info_span!("storage_download")).await {
+            Ok(data) => {
+                metrics::BLOB_RECOVERY_TOTAL
+                    .with_label_values(&["storage"])
+                    .inc();
+                Ok(Blob {
+                    data: data.into(),
+                    quorum_id,
+                    source: BlobSource::StorageNode,
+                })
+            }
+            Err(e) => {
+                warn!("storage nodes fallback failed, error: {:?}", e);
+                Err(err)
+            }
+        }
+    }
+
+    /// Fetches the blob from the disperser, whose reply is only used once it
+    /// matches the storage root.
+    async fn retrieve_from_disperser(
+        &self,
+        disperser: &Disperser,
+        data_root: &[u8],
+        epoch: u64,
+        quorum_id: u64,
+    ) -> anyhow::Result<Vec<u8>> {
+        let data = disperser
+            .retrieve_blob(data_root.to_vec(), epoch, quorum_id)
+            .instrument(info_span!("disperser_lookup"))
+            .await?;
+        if data.is_empty() {
+            bail!("disperser returned an empty blob");
+        }
+
+        // the reply is the blob itself, returned as is once it matches
+        info_span!("disperser_check").in_scope(|| storage::check_file_root(&data, data_root))?;
+        Ok(data)
+    }
+
+    async fn retrieve_blob_inner(
+        &self,
+        data_root: Vec<u8>,
+        epoch: u64,
+        quorum_id: u64,
+    ) -> Result<Vec<u8>, RetrieveError> {
+        let ts = Instant::now();
+
+        let quorum = self.get_quorum(epoch, quorum_id).await?;
+        let request = RowRequest {
+            data_root: &data_root,
+            epoch,
+            quorum_id,
+            signers: &quorum.signers,
+            invalid_signers: Arc::new(Mutex::new(HashSet::new())),
+        };
+        let required_rows = quorum.params.required_rows();
+        let systematic_rows = quorum.params.systematic_rows;
+
+        let mut tasks = vec![];
+        for (address, indices) in quorum.systematic_rows.iter() {
+            tasks.push(self.spawn_request_task(&request, *address, indices.clone())?);
+        }
+
+        let mut rows = ReceivedRows::default();
+        // looked up while the signers answer
+        let (length, ()) = tokio::join!(self.blob_length(&data_root), async {
+            for task in tasks {
+                wait_request_task(task, &mut rows).await;
+            }
+        });
+
+        info!("ready slices length {:?}", rows.len());
+
+        let mut pending: Vec<_> = quorum.parity_rows.clone().into_iter().collect();
+        self.fetch_rows(&request, &mut pending, &mut rows, None, required_rows)
+            .await?;
+        if rows.len() < required_rows {
+            return Err(RetrieveError::InsufficientRows {
+                available: rows.len(),
+                required: required_rows,
+            });
+        }
+
+        info!("start recover {:?} ms", ts.elapsed().as_millis());
+        let err = match recover_blob(&rows.slices, systematic_rows, &data_root, length) {
+            Ok((data, path)) => {
+                self.accept_rows(&request, &rows, None);
+                metrics::BLOB_RECOVERY_TOTAL
+                    .with_label_values(&[path])
+                    .inc();
+                info!(path, "response in {:?} ms", ts.elapsed().as_millis());
+                return Ok(data);
+            }
+            Err(e) => e,
+        };
+
+        // some signer served rows not matching the storage root, the rows of
+        // each signer are replaced by parity rows in turn to find it
+        warn!(
+            "rows rejected, look for the signer serving them, error: {:?}",
+            err
+        );
+        let suspects: Vec<H160> = rows.served_by.keys().copied().collect();
+        for suspect in suspects {
+            self.fetch_rows(
+                &request,
+                &mut pending,
+                &mut rows,
+                Some(suspect),
+                required_rows,
+            )
+            .await?;
+            let slices = rows.without(suspect);
+            if slices.len() < required_rows {
+                continue;
+            }
+
+            if let Ok((data, path)) = recover_blob(&slices, systematic_rows, &data_root, length) {
+                warn!(signer = ?suspect, "signer served rows not matching the storage root");
+                request.invalid_signers.lock().await.insert(suspect);
+                self.accept_rows(&request, &rows, Some(suspect));
+                metrics::BLOB_RECOVERY_TOTAL
+                    .with_label_values(&[path])
+                    .inc();
+                info!(path, "response in {:?} ms", ts.elapsed().as_millis());
+                return Ok(data);
+            }
+        }
+
+        Err(err)
+    }
+
+    /// Hands the rows a verified blob was recovered from, those of `excluded`
+    /// left out, back to the slice source, which may keep them.
+    fn accept_rows(&self, request: &RowRequest<'_>, rows: &ReceivedRows, excluded: Option<H160>) {
+        for (address, row_indexes, slices) in rows.by_signer(excluded) {
+            let detail = match request.signers.get(&address) {
+                Some(detail) => detail,
+                None => continue,
+            };
+            let signer = SignerEndpoint {
+                address,
+                socket: detail.socket.clone(),
+            };
+            let param = RetrieveParam {
+                epoch: request.epoch,
+                quorum_id: request.quorum_id,
+                storage_root: request.data_root.to_vec(),
+                row_indexes,
+            };
+            self.slice_source.accept(&signer, &param, &slices);
+        }
+    }
+
+    /// Asks the signers of `pending` for their rows, batch after batch, until
+    /// `required` rows not served by `excluded` are available or no signer is
+    /// left. The rows of `excluded` stay pending.
+    async fn fetch_rows(
+        &self,
+        request: &RowRequest<'_>,
+        pending: &mut Vec<(H160, Vec<u32>)>,
+        rows: &mut ReceivedRows,
+        excluded: Option<H160>,
+        required: usize,
+    ) -> Result<(), RetrieveError> {
+        while rows.count_without(excluded) < required {
+            let mut tasks = vec![];
+            let mut requesting = 0;
+            let mut kept = vec![];
+            while let Some((address, indices)) = pending.pop() {
+                if Some(address) == excluded {
+                    kept.push((address, indices));
+                    continue;
+                }
+                if request.invalid_signers.lock().await.contains(&address) {
+                    continue;
+                }
+
+                requesting += indices.len();
+                tasks.push(self.spawn_request_task(request, address, indices)?);
+                if rows.count_without(excluded) + requesting >= required {
+                    break;
+                }
+            }
+            pending.extend(kept);
+
+            if tasks.is_empty() {
+                error!("no eligible signers available for data request");
+                break;
+            }
+            for task in tasks {
+                wait_request_task(task, rows).await;
+            }
+
+            info!("ready slices length {:?}", rows.len());
+        }
+
+        Ok(())
+    }
+
+    /// Length of the blob as submitted to 0G storage, if storage nodes are
+    /// configured and know the blob.
+    async fn blob_length(&self, data_root: &[u8]) -> Option<usize> {
+        let storage_nodes = self.storage_nodes.as_ref()?;
+        match storage_nodes
+            .file_size(data_root)
+            .instrument(info_span!("storage_lookup"))
+            .await
+        {
+            Ok(size) => usize::try_from(size).ok(),
+            Err(e) => {
+                debug!("blob length unavailable, error: {:?}", e);
+                None
+            }
+        }
+    }
+
+    async fn retrieve_range_inner(
+        &self,
+        data_root: Vec<u8>,
+        epoch: u64,
+        quorum_id: u64,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, RetrieveError> {
+        let ts = Instant::now();
+
+        if length == 0 {
+            return Err(RetrieveError::InvalidRange("range length is zero".into()));
+        }
+        let end = offset
+            .checked_add(length)
+            .ok_or(RetrieveError::OutOfRange("range end overflows".into()))?;
+
+        let quorum = self.get_quorum(epoch, quorum_id).await?;
+
+        let rows = layout::rows_for_range(offset, length, quorum.params.systematic_rows)
+            .ok_or_else(|| {
+                RetrieveError::OutOfRange(format!(
+                    "range end {} exceeds blob capacity {}",
+                    end,
+                    quorum.params.systematic_rows * ROW_BYTES
+                ))
+            })?;
+
+        // only the owners of the rows covering the range are asked
+        let request = RowRequest {
+            data_root: &data_root,
+            epoch,
+            quorum_id,
+            signers: &quorum.signers,
+            invalid_signers: Arc::new(Mutex::new(HashSet::new())),
+        };
+        let mut tasks = vec![];
+        for (address, indices) in quorum.systematic_rows.iter() {
+            let indices: Vec<u32> = indices
+                .iter()
+                .copied()
+                .filter(|i| rows.contains(i))
+                .collect();
+            if indices.is_empty() {
+                continue;
+            }
+
+            tasks.push(self.spawn_request_task(&request, *address, indices)?);
+        }
+
+        let mut received = ReceivedRows::default();
+        for task in tasks {
+            wait_request_task(task, &mut received).await;
+        }
+
+        let base = rows.start as u64 * ROW_BYTES as u64;
+        let assembled = rows
+            .clone()
+            .map(|row| {
+                received
+                    .slices
+                    .get(&(row as usize))
+                    .ok_or(anyhow::anyhow!("row {} is unavailable", row))
+                    .and_then(|slice| layout::decode_systematic_row(row, slice))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map(|rows| rows.concat());
+
+        match assembled {
+            Ok(data) => {
+                info!(
+                    "range served from {} rows in {:?} ms",
+                    rows.len(),
+                    ts.elapsed().as_millis()
+                );
+                slice_range(data, offset - base, end - base)
+            }
+            Err(e) => {
+                warn!(
+                    "range not servable from its rows, fall back to full recovery, error: {:?}",
+                    e
+                );
+                let blob = self
+                    .retrieve_blob_inner(data_root, epoch, quorum_id)
+                    .await?;
+                slice_range(blob, offset, end)
+            }
+        }
+    }
+
+    fn spawn_request_task(
+        &self,
+        request: &RowRequest<'_>,
+        address: H160,
+        indices: Vec<u32>,
+    ) -> Result<SliceTask, RetrieveError> {
+        let signer = SignerEndpoint {
+            address,
+            socket: request
+                .signers
+                .get(&address)
+                .ok_or(RetrieveError::Internal(format!(
+                    "signer {:?} does't exist",
+                    address
+                )))?
+                .socket
+                .clone(),
+        };
+
+        let slice_source = self.slice_source.clone();
+        let data_root = request.data_root.to_vec();
+        let (epoch, quorum_id) = (request.epoch, request.quorum_id);
+        let invalid_signers = Arc::clone(&request.invalid_signers);
+        // child of the request span, so the signer logs carry the request ID
+        let span = info_span!(
+            "request_slice",
+            signer = ?address,
+            socket = %signer.socket,
+            rows = indices.len()
+        );
+        let task = async move {
+            let res: SliceResult = async move {
+                let response = slice_source
+                    .get_slices(
+                        &signer,
+                        vec![RetrieveParam {
+                            epoch: epoch,
+                            quorum_id,
+                            storage_root: data_root,
+                            row_indexes: indices.clone(),
+                        }],
+                    )
+                    .await;
+
+                // a signer answering malformed slices is not asked again
+                let err =
+                    match response {
+                        Ok(mut response) => match response.pop() {
+                            Some(s) if s.len() == indices.len() => {
+                                match indices.iter().zip(&s).try_for_each(|(row, slice)| {
+                                    layout::check_row_index(*row, slice)
+                                }) {
+                                    Ok(()) => return Ok((address, indices, s)),
+                                    Err(e) => e,
+                                }
+                            }
+                            Some(s) => SliceError::RowCount {
+                                request: 0,
+                                expected: indices.len(),
+                                got: s.len(),
+                            }
+                            .into(),
+                            None => anyhow::anyhow!("slice is empty"),
+                        },
+                        Err(e) if e.downcast_ref::<SliceError>().is_some() => e,
+                        Err(e) => return Err(e),
+                    };
+                invalid_signers.lock().await.insert(address);
+                Err(err)
+            }
+            .await;
+
+            if let Err(e) = &res {
+                error!("retrieve slice failed, error: {:?}", e);
+            }
+            res
+        }
+        .instrument(span);
+
+        let task = match self.executor.as_ref() {
+            Some(executor) => executor
+                .spawn_handle(task, "request slice")
+                .ok_or(RetrieveError::Internal(
+                    "failed to spawn request slice".into(),
+                ))?
+                .map(|res| {
+                    res.unwrap_or_else(|e| {
+                        error!("join error: {:?}", e);
+                        None
+                    })
+                })
+                .boxed(),
+            None => tokio::spawn(task)
+                .map(|res| res.map_err(|e| error!("join error: {:?}", e)).ok())
+                .boxed(),
+        };
+
+        Ok(task)
+    }
+}
+
+/// Decodes the blob from `slices`, concatenating the systematic rows if all
+/// are available, and checks it against the storage root. Returns the blob
+/// and the recovery path taken.
+fn recover_blob(
+    slices: &BTreeMap<usize, Vec<u8>>,
+    systematic_rows: usize,
+    data_root: &[u8],
+    length: Option<usize>,
+) -> Result<(Vec<u8>, &'static str), RetrieveError> {
+    let recovery_error = |e: anyhow::Error| RetrieveError::Recovery(format!("{:?}", e));
+    let (data, path) = if (0..systematic_rows).all(|row| slices.contains_key(&row)) {
+        let data = info_span!("recover", path = "systematic")
+            .in_scope(|| layout::assemble_systematic_rows(slices, systematic_rows))
+            .map_err(recovery_error)?;
+        (data, "systematic")
+    } else {
+        let data = info_span!("recover", path = "erasure", slices = slices.len())
+            .in_scope(|| recover_from_da_slice(slices))
+            .map_err(|e| RetrieveError::Recovery(format!("{:?}", e)))?;
+        (data, "erasure")
+    };
+
+    Ok((
+        verify_blob(data, data_root, length).map_err(recovery_error)?,
+        path,
+    ))
+}
+
+/// Checks decoded `data` against the storage root, and cuts it to the blob
+/// `length` if known. The zero padding of the encoder is kept otherwise, the
+/// blob length is not part of the encoded rows.
+fn verify_blob(
+    mut data: Vec<u8>,
+    data_root: &[u8],
+    length: Option<usize>,
+) -> anyhow::Result<Vec<u8>> {
+    if let Some(length) = length.filter(|length| *length <= data.len()) {
+        if storage::check_file_root(&data[..length], data_root).is_ok() {
+            data.truncate(length);
+            return Ok(data);
+        }
+        warn!(
+            length,
+            "blob length of the storage nodes does not match, keep the padding"
+        );
+    }
+
+    storage::check_padded_file_root(&data, data_root)?;
+    Ok(data)
+}
+
+fn slice_range(mut data: Vec<u8>, start: u64, end: u64) -> Result<Vec<u8>, RetrieveError> {
+    if end > data.len() as u64 {
+        return Err(RetrieveError::OutOfRange(format!(
+            "range end {} exceeds blob size {}",
+            end,
+            data.len()
+        )));
+    }
+
+    data.truncate(end as usize);
+    data.drain(..start as usize);
+    Ok(data)
+}
+
+async fn wait_request_task(task: SliceTask, rows: &mut ReceivedRows) {
+    match task.await {
+        Some(Ok((address, indices, s))) => rows.insert(address, indices, s),
+        // logged by the task, within the signer span
+        Some(Err(_)) => {}
+        None => {
+            error!("slice is None");
+        }
+    }
+}
@@ -1,269 +1,281 @@
-use std::{
-    collections::{BTreeMap, HashMap, HashSet},
-    sync::Arc,
-    time::Instant,
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
 };
 
-use anyhow::bail;
-use contract_provider::{ContractProvider, SignerDetail, MIN_REQUIRED_SLICE};
-use ethers::types::H160;
-use retriever::{retriever_server::Retriever, BlobReply, BlobRequest};
-use signer_provider::{RetrieveParam, SignerProvider};
-use task_executor::TaskExecutor;
+use ethers::utils::hex;
+use opentelemetry::{global, propagation::Extractor};
+use retriever::{retriever_server, BlobRangeRequest, BlobReply, BlobRequest, ServedBy};
 use tokio::sync::{Mutex, RwLock};
-use tonic::{Code, Request, Response, Status};
-use zg_da_recovery::recover_from_da_slice;
+use tonic::{metadata::MetadataMap, Code, Request, Response, Status};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+use crate::{
+    rate_limit::RateLimiter,
+    retrieval::{BlobSource, RetrieveError, Retriever},
+};
 
 const DEFAULT_MAX_ONGOING_SIGN_REQUEST: u64 = 10;
+const MESSAGE_SIZE_LIMIT: usize = 1024 * 1024 * 1024; // 1G
+
+/// Metadata key carrying the request ID. A caller supplied ID is kept so that
+/// logs can be correlated across services, otherwise one is generated.
+const REQUEST_ID_KEY: &str = "x-request-id";
 
 pub mod retriever {
     tonic::include_proto!("retriever");
+
+    impl BlobRequest {
+        /// Requests the blob from `quorum_id`, from any quorum if `None`.
+        pub fn new(storage_root: Vec<u8>, epoch: u64, quorum_id: Option<u64>) -> Self {
+            Self {
+                storage_root,
+                epoch,
+                quorum_id: quorum_id.unwrap_or_default(),
+                any_quorum: quorum_id.is_none(),
+            }
+        }
+
+        /// The requested quorum, `None` for any quorum.
+        pub fn quorum(&self) -> Option<u64> {
+            (!self.any_quorum).then_some(self.quorum_id)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ServiceConfig {
+    pub max_ongoing_retrieve_request: u64,
+    /// Retrieve requests admitted per second, unlimited if zero.
+    pub rate_limit_per_sec: u64,
+    pub rate_limit_burst: u64,
+    /// Limit of the gRPC server, only applied when it starts.
+    pub max_message_size: usize,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            max_ongoing_retrieve_request: DEFAULT_MAX_ONGOING_SIGN_REQUEST,
+            rate_limit_per_sec: 0,
+            rate_limit_burst: 0,
+            max_message_size: MESSAGE_SIZE_LIMIT,
+        }
+    }
 }
 
+/// Serves a [`Retriever`] over gRPC, admitting a limited number of requests.
 pub struct RetrieverService {
-    contract_provider: ContractProvider,
-    signer_provider: Arc<SignerProvider>,
-    executor: TaskExecutor,
+    // shared with the config reloader, which updates its deny list
+    retriever: Arc<Retriever>,
 
-    max_ongoing_retrieve_request: u64,
-    ongoing_retrieve_request_cnt: Arc<RwLock<u64>>,
+    // shared with the config reloader
+    config: Arc<RwLock<ServiceConfig>>,
+    rate_limiter: Mutex<RateLimiter>,
+    ongoing_retrieve_request_cnt: Arc<AtomicU64>,
+}
+
+/// Admission slot of a retrieve request, given back when dropped, so that a
+/// cancelled or panicking call does not hold it.
+struct Admission(Arc<AtomicU64>);
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 #[tonic::async_trait]
-impl Retriever for RetrieverService {
+impl retriever_server::Retriever for RetrieverService {
     async fn retrieve_blob(
         &self,
         request: Request<BlobRequest>,
     ) -> Result<Response<BlobReply>, Status> {
-        self.on_incoming_retrieve_request().await?;
-
+        let request_id = request_id(request.metadata());
         let remote_addr = request.remote_addr();
-        info!(?remote_addr, "Received request");
-
-        let message = request.into_inner();
-        let reply = self
-            .retrieve_blob_inner(message.storage_root, message.epoch, message.quorum_id)
-            .await;
-
-        self.on_complete_retrieve_request().await;
+        let (metadata, _, message) = request.into_parts();
+        let span = info_span!(
+            "retrieve_blob",
+            %request_id,
+            storage_root = %hex::encode(&message.storage_root),
+            epoch = message.epoch,
+            quorum_id = ?message.quorum(),
+        );
+        span.set_parent(remote_context(&metadata));
+
+        async move {
+            let _admission = self.on_incoming_retrieve_request().await?;
+            info!(?remote_addr, "Received request");
+
+            let reply = self
+                .retriever
+                .retrieve(&message.storage_root, message.epoch, message.quorum())
+                .await
+                .map(|blob| BlobReply {
+                    data: blob.data.into(),
+                    quorum_id: blob.quorum_id,
+                    served_by: ServedBy::from(blob.source).into(),
+                })
+                .map_err(Status::from);
+
+            reply.map(|reply| with_request_id(Response::new(reply), &request_id))
+        }
+        .instrument(span)
+        .await
+    }
 
-        reply
+    async fn retrieve_blob_range(
+        &self,
+        request: Request<BlobRangeRequest>,
+    ) -> Result<Response<BlobReply>, Status> {
+        let request_id = request_id(request.metadata());
+        let remote_addr = request.remote_addr();
+        let (metadata, _, message) = request.into_parts();
+        let span = info_span!(
+            "retrieve_blob_range",
+            %request_id,
+            storage_root = %hex::encode(&message.storage_root),
+            epoch = message.epoch,
+            quorum_id = message.quorum_id,
+            offset = message.offset,
+            length = message.length,
+        );
+        span.set_parent(remote_context(&metadata));
+
+        async move {
+            let _admission = self.on_incoming_retrieve_request().await?;
+            info!(?remote_addr, "Received range request");
+
+            let reply = self
+                .retriever
+                .retrieve_range(
+                    &message.storage_root,
+                    message.epoch,
+                    message.quorum_id,
+                    message.offset,
+                    message.length,
+                )
+                .await
+                .map(|data| BlobReply {
+                    data: data.into(),
+                    quorum_id: message.quorum_id,
+                    served_by: ServedBy::Signers.into(),
+                })
+                .map_err(Status::from);
+
+            reply.map(|reply| with_request_id(Response::new(reply), &request_id))
+        }
+        .instrument(span)
+        .await
     }
 }
 
 impl RetrieverService {
     pub fn new(
-        contract_provider: ContractProvider,
-        signer_provider: Arc<SignerProvider>,
-        max_ongoing_sign_request: Option<u64>,
-        executor: TaskExecutor,
+        retriever: Arc<Retriever>,
+        config: Arc<RwLock<ServiceConfig>>,
+        ongoing_retrieve_request_cnt: Arc<AtomicU64>,
     ) -> Self {
         Self {
-            contract_provider,
-            signer_provider,
-            executor,
-            max_ongoing_retrieve_request: max_ongoing_sign_request
-                .unwrap_or(DEFAULT_MAX_ONGOING_SIGN_REQUEST),
-            ongoing_retrieve_request_cnt: Arc::new(RwLock::new(0)),
-        }
-    }
-
-    async fn on_incoming_retrieve_request(&self) -> Result<(), Status> {
-        let mut cnt = self.ongoing_retrieve_request_cnt.write().await;
-        if *cnt > self.max_ongoing_retrieve_request {
-            return Err(Status::new(Code::ResourceExhausted, "request pool is full"));
+            retriever,
+            config,
+            rate_limiter: Mutex::new(RateLimiter::new()),
+            ongoing_retrieve_request_cnt,
         }
-        *cnt += 1;
-        Ok(())
-    }
-
-    async fn on_complete_retrieve_request(&self) {
-        let mut cnt = self.ongoing_retrieve_request_cnt.write().await;
-        *cnt -= 1;
     }
 
-    async fn retrieve_blob_inner(
-        &self,
-        data_root: Vec<u8>,
-        epoch: u64,
-        quorum_id: u64,
-    ) -> Result<Response<BlobReply>, Status> {
-        let ts = Instant::now();
-
-        let (signers, signer_first_1024_slices, signer_slices) = self
-            .contract_provider
-            .get_signers(epoch, quorum_id)
-            .await
-            .map_err(|e| {
-                Status::new(
-                    Code::NotFound,
-                    format!("failed get signers from block chain: {:?}", e),
-                )
-            })?;
-
-        let mut tasks = vec![];
-        let invalid_signers = Arc::new(Mutex::new(HashSet::new()));
-        for (address, indices) in signer_first_1024_slices.into_iter() {
-            let invalid_signers = Arc::clone(&invalid_signers);
-            let task = self.spawn_request_task(
-                &data_root,
-                epoch,
-                quorum_id,
-                address,
-                indices,
-                &signers,
-                invalid_signers,
-            )?;
-
-            tasks.push(task);
-        }
+    async fn on_incoming_retrieve_request(&self) -> Result<Admission, Status> {
+        let (max_ongoing_retrieve_request, rate, burst) = {
+            let config = self.config.read().await;
+            (
+                config.max_ongoing_retrieve_request,
+                config.rate_limit_per_sec,
+                config.rate_limit_burst,
+            )
+        };
 
-        let mut input_slices: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
-        for task in tasks {
-            wait_request_task(task, &mut input_slices).await;
+        if rate > 0 && !self.rate_limiter.lock().await.try_acquire(rate, burst) {
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                "request rate limit exceeded",
+            ));
         }
 
-        info!("ready slices length {:?}", input_slices.len());
-
-        let mut iter = signer_slices.into_iter();
-        let mut running = true;
-        while input_slices.len() < MIN_REQUIRED_SLICE && running {
-            let mut tasks = vec![];
-            let mut new_requesting_slice_length = 0;
-
-            loop {
-                let (address, indices) = if let Some((address, indices)) = iter.next() {
-                    (address, indices)
-                } else {
-                    error!("no eligible signers available for data request");
-                    running = false;
-                    break;
-                };
-
-                if invalid_signers.lock().await.contains(&address) {
-                    continue;
-                }
-
-                let slice_len = indices.len();
-                let invalid_signers = Arc::clone(&invalid_signers);
-                let task = self.spawn_request_task(
-                    &data_root,
-                    epoch,
-                    quorum_id,
-                    address,
-                    indices,
-                    &signers,
-                    invalid_signers,
-                )?;
-
-                tasks.push(task);
-
-                new_requesting_slice_length += slice_len;
-                if new_requesting_slice_length + input_slices.len() >= MIN_REQUIRED_SLICE {
-                    break;
-                }
-            }
+        self.ongoing_retrieve_request_cnt
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |cnt| {
+                (cnt < max_ongoing_retrieve_request).then_some(cnt + 1)
+            })
+            .map_err(|_| Status::new(Code::ResourceExhausted, "request pool is full"))?;
+        Ok(Admission(self.ongoing_retrieve_request_cnt.clone()))
+    }
+}
 
-            for task in tasks {
-                wait_request_task(task, &mut input_slices).await;
+impl From<RetrieveError> for Status {
+    fn from(e: RetrieveError) -> Self {
+        let code = match &e {
+            RetrieveError::Registry(_) => Code::Unavailable,
+            RetrieveError::NoQuorum(_)
+            | RetrieveError::InsufficientRows { .. }
+            | RetrieveError::Recovery(_) => Code::NotFound,
+            RetrieveError::InvalidStorageRoot(_) | RetrieveError::InvalidRange(_) => {
+                Code::InvalidArgument
             }
-
-            info!("ready slices length {:?}", input_slices.len());
-        }
-
-        info!("start recover {:?} ms", ts.elapsed().as_millis());
-        let reply = BlobReply {
-            data: recover_from_da_slice(&input_slices).map_err(|e| {
-                Status::new(Code::NotFound, format!("fail to recover slice: {:?}", e))
-            })?,
+            RetrieveError::EpochNotReached { .. }
+            | RetrieveError::UnknownQuorum { .. }
+            | RetrieveError::OutOfRange(_) => Code::OutOfRange,
+            RetrieveError::Internal(_) => Code::Internal,
         };
-
-        info!("response in {:?} ms", ts.elapsed().as_millis());
-        Ok(Response::new(reply))
+        Status::new(code, e.to_string())
     }
+}
 
-    fn spawn_request_task(
-        &self,
-        data_root: &Vec<u8>,
-        epoch: u64,
-        quorum_id: u64,
-        address: H160,
-        indices: Vec<u32>,
-        signers: &HashMap<H160, SignerDetail>,
-        invalid_signers: Arc<Mutex<HashSet<H160>>>,
-    ) -> Result<
-        task_executor::JoinHandle<Option<Result<(Vec<u32>, Vec<Vec<u8>>), anyhow::Error>>>,
-        Status,
-    > {
-        let mut socket = signers
-            .get(&address)
-            .ok_or(Status::new(Code::InvalidArgument, "signer does't exist"))?
-            .socket
-            .clone();
-
-        if !socket.to_lowercase().starts_with("http://") {
-            socket = format!("http://{}", socket);
+impl From<BlobSource> for ServedBy {
+    fn from(source: BlobSource) -> Self {
+        match source {
+            BlobSource::Signers => ServedBy::Signers,
+            BlobSource::Disperser => ServedBy::Disperser,
+            BlobSource::StorageNode => ServedBy::StorageNode,
         }
+    }
+}
 
-        let signer_provider = self.signer_provider.clone();
-        let data_root = data_root.clone();
-        let task = self
-            .executor
-            .spawn_handle(
-                async move {
-                    let mut response = signer_provider
-                        .get_slices(
-                            socket,
-                            vec![RetrieveParam {
-                                epoch: epoch,
-                                quorum_id,
-                                storage_root: data_root,
-                                row_indexes: indices.clone(),
-                            }],
-                        )
-                        .await?;
-
-                    match response.pop() {
-                        Some(s) => Ok((indices, s)),
-                        None => {
-                            invalid_signers.lock().await.insert(address);
-                            bail!("slice is empty")
-                        }
-                    }
-                },
-                "request slice",
-            )
-            .ok_or(Status::new(Code::Internal, "failed to spawn request slice"))?;
+fn request_id(metadata: &MetadataMap) -> String {
+    metadata
+        .get(REQUEST_ID_KEY)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(str::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
 
-        Ok(task)
+struct MetadataExtractor<'a>(&'a MetadataMap);
+
+impl<'a> Extractor for MetadataExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .filter_map(|key| match key {
+                tonic::metadata::KeyRef::Ascii(key) => Some(key.as_str()),
+                tonic::metadata::KeyRef::Binary(_) => None,
+            })
+            .collect()
     }
 }
 
-async fn wait_request_task(
-    task: task_executor::JoinHandle<Option<Result<(Vec<u32>, Vec<Vec<u8>>), anyhow::Error>>>,
-    slices: &mut BTreeMap<usize, Vec<u8>>,
-) {
-    match task.await {
-        Ok(r) => match r {
-            Some(v) => match v {
-                Ok((indices, s)) => {
-                    indices
-                        .into_iter()
-                        .zip(s.into_iter())
-                        .for_each(|(key, value)| {
-                            slices.insert(key as usize, value);
-                        });
-                }
-                Err(e) => {
-                    error!("retrieve slice failed, error: {:?}", e);
-                }
-            },
-            None => {
-                error!("slice is None");
-            }
-        },
-        Err(e) => {
-            error!("join error: {:?}", e);
-        }
+/// Trace context of the caller, propagated in the `traceparent` metadata.
+fn remote_context(metadata: &MetadataMap) -> opentelemetry::Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&MetadataExtractor(metadata)))
+}
+
+fn with_request_id<T>(mut response: Response<T>, request_id: &str) -> Response<T> {
+    if let Ok(value) = request_id.parse() {
+        response.metadata_mut().insert(REQUEST_ID_KEY, value);
     }
+    response
 }
@@ -0,0 +1,543 @@
+//! Download of blobs from 0G storage nodes, which also keep every blob as a
+//! file under its storage root.
+//!
+//! A file is split into segments of `SEGMENT_ENTRIES` entries of `ENTRY_SIZE`
+//! bytes, the last segment holding the remaining entries, zero padded. The
+//! root of a segment is the keccak256 merkle root of its entry hashes, and
+//! the file root, i.e. the storage root, the one of the segment roots. In
+//! both trees an unpaired node is carried up to the next level as is. Every
+//! segment comes with the proof of its root up to the file root.
+
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{anyhow, bail, Result};
+use ethers::{
+    providers::{Http, JsonRpcClient},
+    types::H256,
+    utils::keccak256,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub const ENTRY_SIZE: usize = 256;
+pub const SEGMENT_ENTRIES: usize = 1024;
+pub const SEGMENT_SIZE: usize = ENTRY_SIZE * SEGMENT_ENTRIES;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageNodeConfig {
+    /// JSON RPC endpoints of the storage nodes, tried in turn.
+    pub urls: Vec<String>,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileInfo {
+    tx: Transaction,
+    finalized: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Transaction {
+    size: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SegmentWithProof {
+    root: H256,
+    #[serde(with = "base64_bytes")]
+    data: Vec<u8>,
+    index: usize,
+    proof: FlowProof,
+    file_size: usize,
+}
+
+/// Merkle proof of a node, `lemma` holding the node, its siblings from the
+/// bottom up and the root. `path` tells for every sibling whether the node
+/// is on the left of it.
+#[derive(Debug, Deserialize, Serialize)]
+struct FlowProof {
+    lemma: Vec<H256>,
+    path: Vec<bool>,
+}
+
+/// Storage nodes serving files by storage root.
+pub struct StorageNodes {
+    nodes: Vec<(String, Http)>,
+    timeout: Duration,
+}
+
+impl StorageNodes {
+    pub fn new(config: &StorageNodeConfig) -> Result<Self> {
+        let mut nodes = vec![];
+        for url in config.urls.iter() {
+            nodes.push((url.clone(), Http::from_str(url)?));
+        }
+
+        Ok(Self {
+            nodes,
+            timeout: config.timeout,
+        })
+    }
+
+    /// Downloads the file of `storage_root` from the first node serving it
+    /// with valid proofs, refusing files larger than `max_size`.
+    pub async fn download(&self, storage_root: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        if storage_root.len() != H256::len_bytes() {
+            bail!("invalid storage root length {}", storage_root.len());
+        }
+        let root = H256::from_slice(storage_root);
+
+        let mut last_err = anyhow!("no storage node configured");
+        for (url, client) in self.nodes.iter() {
+            match self.download_from(client, root, max_size).await {
+                Ok(data) => {
+                    info!(node = %url, size = data.len(), "file downloaded from storage node");
+                    return Ok(data);
+                }
+                Err(e) => {
+                    warn!(node = %url, "download from storage node failed, error: {:?}", e);
+                    last_err = e;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+
+    /// Size of the file of `storage_root`, as submitted on chain, from the
+    /// first node holding the file. Not proven, a node may lie about it.
+    pub async fn file_size(&self, storage_root: &[u8]) -> Result<u64> {
+        if storage_root.len() != H256::len_bytes() {
+            bail!("invalid storage root length {}", storage_root.len());
+        }
+        let root = H256::from_slice(storage_root);
+
+        let mut last_err = anyhow!("no storage node configured");
+        for (url, client) in self.nodes.iter() {
+            match self.file_info(client, root).await {
+                Ok(info) => return Ok(info.tx.size),
+                Err(e) => {
+                    debug!(node = %url, "file info from storage node failed, error: {:?}", e);
+                    last_err = e;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+
+    async fn file_info(&self, client: &Http, root: H256) -> Result<FileInfo> {
+        let info: FileInfo = self
+            .request::<_, Option<FileInfo>>(client, "zgs_getFileInfo", [root])
+            .await?
+            .ok_or(anyhow!("file not found"))?;
+        if !info.finalized {
+            bail!("file not finalized");
+        }
+        Ok(info)
+    }
+
+    async fn download_from(&self, client: &Http, root: H256, max_size: usize) -> Result<Vec<u8>> {
+        let info = self.file_info(client, root).await?;
+
+        // the size is not proven yet, the buffer only grows with verified
+        // segments
+        let size = match usize::try_from(info.tx.size) {
+            Ok(size) if size <= max_size => size,
+            _ => bail!("file size {} exceeds {}", info.tx.size, max_size),
+        };
+        let segments = size.div_ceil(SEGMENT_SIZE);
+        let mut data = vec![];
+        for index in 0..segments {
+            let segment: SegmentWithProof = self
+                .request::<_, Option<SegmentWithProof>>(
+                    client,
+                    "zgs_downloadSegmentWithProof",
+                    (root, index),
+                )
+                .await?
+                .ok_or(anyhow!("segment {} not found", index))?;
+            segment.verify(root, index, size)?;
+            data.extend_from_slice(&segment.data);
+        }
+
+        data.truncate(size);
+        Ok(data)
+    }
+
+    async fn request<P, R>(&self, client: &Http, method: &str, params: P) -> Result<R>
+    where
+        P: std::fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        match tokio::time::timeout(self.timeout, client.request(method, params)).await {
+            Ok(res) => Ok(res?),
+            Err(_) => bail!("{} timed out", method),
+        }
+    }
+}
+
+impl SegmentWithProof {
+    fn verify(&self, root: H256, index: usize, file_size: usize) -> Result<()> {
+        if self.root != root || self.index != index || self.file_size != file_size {
+            bail!("segment {} does not match the request", index);
+        }
+
+        let remaining = (file_size - index * SEGMENT_SIZE).min(SEGMENT_SIZE);
+        let expected_len = remaining.div_ceil(ENTRY_SIZE) * ENTRY_SIZE;
+        if self.data.len() != expected_len {
+            bail!(
+                "segment {} has {} bytes, expect {}",
+                index,
+                self.data.len(),
+                expected_len
+            );
+        }
+
+        let segments = file_size.div_ceil(SEGMENT_SIZE);
+        self.proof
+            .verify(segment_root(&self.data), root, index, segments)
+    }
+}
+
+impl FlowProof {
+    /// Checks that the proof leads from `node`, leaf `index` of `leaves`, to
+    /// `root`.
+    fn verify(&self, node: H256, root: H256, index: usize, leaves: usize) -> Result<()> {
+        if self.path != proof_path(index, leaves) {
+            bail!("proof is not for leaf {} of {}", index, leaves);
+        }
+        if self.lemma.len() != self.path.len() + 2 - usize::from(self.path.is_empty()) {
+            bail!("invalid proof lemma length {}", self.lemma.len());
+        }
+        if self.lemma.first() != Some(&node) || self.lemma.last() != Some(&root) {
+            bail!("proof does not link the segment to the storage root");
+        }
+
+        let siblings = self.lemma.get(1..self.lemma.len() - 1).unwrap_or_default();
+        let computed = self
+            .path
+            .iter()
+            .zip(siblings)
+            .fold(node, |node, (is_left, sibling)| {
+                if *is_left {
+                    hash_pair(&node, sibling)
+                } else {
+                    hash_pair(sibling, &node)
+                }
+            });
+        if computed != root {
+            bail!("invalid merkle proof");
+        }
+        Ok(())
+    }
+}
+
+/// Expected `FlowProof::path` of leaf `index` of `leaves`, unpaired nodes
+/// having no sibling.
+fn proof_path(mut index: usize, mut leaves: usize) -> Vec<bool> {
+    let mut path = vec![];
+    while leaves > 1 {
+        if index % 2 == 1 {
+            path.push(false);
+        } else if index + 1 < leaves {
+            path.push(true);
+        }
+        index /= 2;
+        leaves = leaves.div_ceil(2);
+    }
+    path
+}
+
+/// Storage root of a file holding `data`.
+pub fn file_root(data: &[u8]) -> H256 {
+    merkle_root(data.chunks(SEGMENT_SIZE).map(segment_root).collect())
+}
+
+/// Checks that `data`, a blob of its exact length, matches the storage root.
+pub(crate) fn check_file_root(data: &[u8], storage_root: &[u8]) -> Result<()> {
+    if storage_root.len() != H256::len_bytes() {
+        bail!("invalid storage root length {}", storage_root.len());
+    }
+    if file_root(data) != H256::from_slice(storage_root) {
+        bail!("data does not match the storage root");
+    }
+    Ok(())
+}
+
+/// Checks `data`, a blob of unknown length followed by zero padding, against
+/// the storage root. The blob is taken to end within the last entry holding
+/// a non zero byte, or to fill `data`, so a blob ending with a whole zero
+/// entry only matches unpadded.
+pub(crate) fn check_padded_file_root(data: &[u8], storage_root: &[u8]) -> Result<()> {
+    let end = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    // the zero bytes ending the last entry leave its hash unchanged
+    if check_file_root(&data[..end], storage_root).is_ok() {
+        return Ok(());
+    }
+    check_file_root(data, storage_root)
+}
+
+/// Root of a segment, the last entry zero padded.
+fn segment_root(data: &[u8]) -> H256 {
+    merkle_root(
+        data.chunks(ENTRY_SIZE)
+            .map(|entry| {
+                let mut padded = [0u8; ENTRY_SIZE];
+                padded[..entry.len()].copy_from_slice(entry);
+                H256(keccak256(padded))
+            })
+            .collect(),
+    )
+}
+
+fn merkle_root(mut level: Vec<H256>) -> H256 {
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash_pair(left, right),
+                _ => pair[0],
+            })
+            .collect();
+    }
+    level.first().copied().unwrap_or_default()
+}
+
+fn hash_pair(left: &H256, right: &H256) -> H256 {
+    H256(keccak256([left.as_bytes(), right.as_bytes()].concat()))
+}
+
+mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, net::TcpListener, sync::Arc};
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Stand-in storage node serving one file, optionally flipping a byte of
+    /// every segment.
+    struct StandIn {
+        data: Vec<u8>,
+        segment_roots: Vec<H256>,
+        tamper: bool,
+    }
+
+    impl StandIn {
+        fn new(data: Vec<u8>, tamper: bool) -> Self {
+            let segment_roots = data
+                .chunks(SEGMENT_SIZE)
+                .map(|segment| segment_root(&padded(segment)))
+                .collect();
+            Self {
+                data,
+                segment_roots,
+                tamper,
+            }
+        }
+
+        fn root(&self) -> H256 {
+            merkle_root(self.segment_roots.clone())
+        }
+
+        fn proof(&self, index: usize) -> FlowProof {
+            let mut lemma = vec![self.segment_roots[index]];
+            let (mut level, mut i) = (self.segment_roots.clone(), index);
+            while level.len() > 1 {
+                let sibling = i ^ 1;
+                if sibling < level.len() {
+                    lemma.push(level[sibling]);
+                }
+                level = level
+                    .chunks(2)
+                    .map(|pair| match pair {
+                        [left, right] => hash_pair(left, right),
+                        _ => pair[0],
+                    })
+                    .collect();
+                i /= 2;
+            }
+            if lemma.len() > 1 {
+                lemma.push(self.root());
+            }
+
+            FlowProof {
+                lemma,
+                path: proof_path(index, self.segment_roots.len()),
+            }
+        }
+
+        fn handle(&self, request: &Value) -> Value {
+            let result = match request["method"].as_str() {
+                Some("zgs_getFileInfo") => json!({
+                    "tx": { "size": self.data.len() },
+                    "finalized": true,
+                }),
+                Some("zgs_downloadSegmentWithProof") => {
+                    let index = request["params"][1].as_u64().unwrap() as usize;
+                    let mut data = padded(self.data.chunks(SEGMENT_SIZE).nth(index).unwrap());
+                    if self.tamper {
+                        data[0] ^= 1;
+                    }
+                    serde_json::to_value(SegmentWithProof {
+                        root: self.root(),
+                        data,
+                        index,
+                        proof: self.proof(index),
+                        file_size: self.data.len(),
+                    })
+                    .unwrap()
+                }
+                _ => Value::Null,
+            };
+            json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+        }
+
+        fn serve(self) -> String {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let stand_in = Arc::new(self);
+            let make_service = make_service_fn(move |_| {
+                let stand_in = stand_in.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        let stand_in = stand_in.clone();
+                        async move {
+                            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                            let reply = stand_in.handle(&serde_json::from_slice(&body).unwrap());
+                            Ok::<_, Infallible>(Response::new(Body::from(reply.to_string())))
+                        }
+                    }))
+                }
+            });
+            tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+            url
+        }
+    }
+
+    const MAX_SIZE: usize = 4 * SEGMENT_SIZE;
+
+    fn padded(segment: &[u8]) -> Vec<u8> {
+        let mut data = segment.to_vec();
+        data.resize(segment.len().div_ceil(ENTRY_SIZE) * ENTRY_SIZE, 0);
+        data
+    }
+
+    #[tokio::test]
+    async fn download_verifies_segments() {
+        let data: Vec<u8> = (0..2 * SEGMENT_SIZE + 1000).map(|i| i as u8).collect();
+        let honest = StandIn::new(data.clone(), false);
+        let root = honest.root();
+        let tampering = StandIn::new(data.clone(), true).serve();
+        let honest = honest.serve();
+
+        let nodes = StorageNodes::new(&StorageNodeConfig {
+            urls: vec![tampering.clone()],
+            timeout: Duration::from_secs(5),
+        })
+        .unwrap();
+        assert!(nodes.download(root.as_bytes(), MAX_SIZE).await.is_err());
+
+        // the tampered segments are rejected, the next node serves the file
+        let nodes = StorageNodes::new(&StorageNodeConfig {
+            urls: vec![tampering, honest],
+            timeout: Duration::from_secs(5),
+        })
+        .unwrap();
+        assert_eq!(
+            nodes.download(root.as_bytes(), MAX_SIZE).await.unwrap(),
+            data
+        );
+        // larger than a blob may be
+        assert!(nodes
+            .download(root.as_bytes(), data.len() - 1)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn download_keeps_trailing_zeros() {
+        let mut data: Vec<u8> = (0..SEGMENT_SIZE + 1000).map(|i| i as u8).collect();
+        data.extend_from_slice(&[0u8; 10]);
+        let node = StandIn::new(data.clone(), false);
+        let root = node.root();
+
+        let nodes = StorageNodes::new(&StorageNodeConfig {
+            urls: vec![node.serve()],
+            timeout: Duration::from_secs(5),
+        })
+        .unwrap();
+        let downloaded = nodes.download(root.as_bytes(), MAX_SIZE).await.unwrap();
+        assert!(check_file_root(&downloaded, root.as_bytes()).is_ok());
+        assert_eq!(downloaded, data);
+    }
+
+    #[test]
+    fn check_padded_file_root_accepts_padding() {
+        let mut data: Vec<u8> = (1..=SEGMENT_SIZE + 1000)
+            .map(|i| (i % 255 + 1) as u8)
+            .collect();
+        // zero bytes of the blob itself
+        data.extend_from_slice(&[0u8; 10]);
+        let root = file_root(&data);
+        assert_eq!(root, StandIn::new(data.clone(), false).root());
+
+        let mut padded = data.clone();
+        padded.resize(3 * SEGMENT_SIZE, 0);
+        assert!(check_padded_file_root(&padded, root.as_bytes()).is_ok());
+        assert!(check_padded_file_root(&data, root.as_bytes()).is_ok());
+
+        // a blob ending with a whole zero entry, unpadded
+        let mut zero_entry = data.clone();
+        zero_entry.extend_from_slice(&[0u8; ENTRY_SIZE]);
+        let zero_entry_root = file_root(&zero_entry);
+        assert!(check_padded_file_root(&zero_entry, zero_entry_root.as_bytes()).is_ok());
+
+        padded[SEGMENT_SIZE] ^= 1;
+        assert!(check_padded_file_root(&padded, root.as_bytes()).is_err());
+        assert!(check_padded_file_root(&data, &[0u8; 32]).is_err());
+    }
+
+    #[test]
+    fn check_file_root_keeps_trailing_zeros() {
+        let mut data: Vec<u8> = (1..=SEGMENT_SIZE + 1000)
+            .map(|i| (i % 255 + 1) as u8)
+            .collect();
+        data.extend_from_slice(&[0u8; 10]);
+        let root = file_root(&data);
+
+        assert!(check_file_root(&data, root.as_bytes()).is_ok());
+        // padding within the last entry leaves the root unchanged, the
+        // exact length is the caller's
+        assert!(check_file_root(&data[..data.len() - 10], root.as_bytes()).is_ok());
+        data[SEGMENT_SIZE] ^= 1;
+        assert!(check_file_root(&data, root.as_bytes()).is_err());
+        assert!(check_file_root(&data, &root.as_bytes()[..31]).is_err());
+    }
+
+    #[test]
+    fn proof_path_skips_unpaired_nodes() {
+        assert_eq!(proof_path(0, 1), Vec::<bool>::new());
+        assert_eq!(proof_path(2, 3), vec![false]);
+        assert_eq!(proof_path(1, 3), vec![false, true]);
+    }
+}
@@ -0,0 +1,240 @@
+//! Retrievals against signers serving the rows of a blob encoded by the DA
+//! encoder, parity rows included, some of them faulty.
+//!
+//! The encoder needs its AMT params, so these tests are ignored unless
+//! `ZG_ENCODER_PARAMS_DIR` points to them:
+//!
+//! ```bash
+//! ZG_ENCODER_PARAMS_DIR=./params cargo test -p grpc --test byzantine -- --ignored
+//! ```
+
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{bail, Result};
+use contract_provider::{MemoryRegistry, RegistrySnapshot, SignerSnapshot};
+use ethers::types::H160;
+use grpc::{BlobSource, RetrieveError};
+use mock_signer::encoder::{EncodedRows, Encoder, ENCODED_ROWS};
+use signer_provider::{
+    FaultySource, RecordingSource, RetrieveParam, SignerEndpoint, SliceCall, SliceFault,
+    SliceSource,
+};
+
+const EPOCH: u64 = 1;
+
+const SYSTEMATIC_ROWS: usize = 1024;
+const SIGNERS: usize = 8;
+
+const ROW_BYTES: usize = 1024 * 31;
+
+fn signer(i: usize) -> H160 {
+    H160::repeat_byte(i as u8 + 1)
+}
+
+/// Owner of a row, the signers take turns, so each holds 384 rows.
+fn owner(row: u32) -> H160 {
+    signer(row as usize % SIGNERS)
+}
+
+/// Shorter than the quorum capacity, the last rows only carry padding.
+const BLOB_SIZE: usize = (SYSTEMATIC_ROWS - 2) * ROW_BYTES + 1000;
+
+fn blob() -> &'static [u8] {
+    static BLOB: OnceLock<Vec<u8>> = OnceLock::new();
+    BLOB.get_or_init(|| (0..BLOB_SIZE).map(|i| (i % 255 + 1) as u8).collect())
+}
+
+/// `blob()` as decoded by the signer path, with the zero padding of the
+/// encoder.
+fn padded_blob() -> Vec<u8> {
+    let mut data = blob().to_vec();
+    data.resize(SYSTEMATIC_ROWS * ROW_BYTES, 0);
+    data
+}
+
+/// `blob()` encoded once for all tests, which takes a while.
+fn encoded() -> &'static EncodedRows {
+    static ENCODED: OnceLock<EncodedRows> = OnceLock::new();
+    ENCODED.get_or_init(|| {
+        let params_dir = std::env::var("ZG_ENCODER_PARAMS_DIR")
+            .expect("ZG_ENCODER_PARAMS_DIR must point to the encoder params");
+        Encoder::new(&params_dir).encode(blob()).unwrap()
+    })
+}
+
+/// Signers serving the encoded rows of `blob()`, each only the rows it owns.
+struct EncodedSigners;
+
+#[tonic::async_trait]
+impl SliceSource for EncodedSigners {
+    async fn get_slices(
+        &self,
+        signer: &SignerEndpoint,
+        params: Vec<RetrieveParam>,
+    ) -> Result<Vec<Vec<Vec<u8>>>> {
+        let encoded = encoded();
+        let mut res = vec![];
+        for param in params {
+            if param.storage_root != encoded.storage_root {
+                bail!("unknown storage root");
+            }
+
+            let mut slices = vec![];
+            for row in param.row_indexes {
+                match encoded.rows.get(row as usize) {
+                    Some(slice) if owner(row) == signer.address => slices.push(slice.clone()),
+                    _ => bail!("{:?} does not store row {}", signer.address, row),
+                }
+            }
+            res.push(slices);
+        }
+
+        Ok(res)
+    }
+}
+
+fn snapshot() -> RegistrySnapshot {
+    let quorum = (0..ENCODED_ROWS as u32).map(owner).collect();
+    RegistrySnapshot {
+        current_epoch: EPOCH,
+        encoded_slices: ENCODED_ROWS,
+        quorums: [(EPOCH, vec![quorum])].into(),
+        signers: (0..SIGNERS)
+            .map(|i| SignerSnapshot {
+                address: signer(i),
+                socket: format!("signer-{}:34000", i),
+                pk_g1: Default::default(),
+                pk_g2: Default::default(),
+            })
+            .collect(),
+    }
+}
+
+struct Retriever {
+    retriever: grpc::Retriever,
+    source: Arc<RecordingSource<FaultySource<EncodedSigners>>>,
+}
+
+impl Retriever {
+    fn new(faults: Vec<(usize, SliceFault)>) -> Self {
+        let mut signers = FaultySource::new(EncodedSigners);
+        for (i, fault) in faults {
+            signers = signers.with_fault(signer(i), fault);
+        }
+        let source = Arc::new(RecordingSource::new(signers));
+        let registry = Arc::new(MemoryRegistry::new(snapshot()).unwrap());
+        Self {
+            retriever: grpc::Retriever::builder(registry, source.clone()).build(),
+            source,
+        }
+    }
+
+    async fn retrieve(&self) -> Result<Vec<u8>, RetrieveError> {
+        let blob = self
+            .retriever
+            .retrieve(&encoded().storage_root, EPOCH, None)
+            .await?;
+        assert_eq!(blob.source, BlobSource::Signers);
+        Ok(blob.data.to_vec())
+    }
+
+    fn calls_to(&self, i: usize) -> Vec<SliceCall> {
+        self.source
+            .calls()
+            .into_iter()
+            .filter(|call| call.signer.address == signer(i))
+            .collect()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs the encoder params, set ZG_ENCODER_PARAMS_DIR"]
+async fn all_signers_honest() {
+    let retriever = Retriever::new(vec![]);
+
+    assert_eq!(retriever.retrieve().await.unwrap(), padded_blob());
+    // the systematic rows suffice
+    assert_eq!(retriever.source.calls().len(), SIGNERS);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs the encoder params, set ZG_ENCODER_PARAMS_DIR"]
+async fn unreachable_signers_are_replaced_by_parity_rows() {
+    // the 3 signers left hold 1152 rows, more than the 1024 required
+    let faults = (0..5)
+        .map(|i| (i, SliceFault::Error("unreachable".into())))
+        .collect();
+    let retriever = Retriever::new(faults);
+
+    assert_eq!(retriever.retrieve().await.unwrap(), padded_blob());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs the encoder params, set ZG_ENCODER_PARAMS_DIR"]
+async fn malformed_replies_are_replaced_by_parity_rows() {
+    let faults = vec![
+        (1, SliceFault::Empty),
+        (3, SliceFault::MissingRows(1)),
+        (5, SliceFault::Corrupt),
+    ];
+    let retriever = Retriever::new(faults);
+
+    assert_eq!(retriever.retrieve().await.unwrap(), padded_blob());
+    // rejected at once, so never asked for their parity rows
+    for i in [1, 3, 5] {
+        assert_eq!(retriever.calls_to(i).len(), 1);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs the encoder params, set ZG_ENCODER_PARAMS_DIR"]
+async fn tampered_rows_are_found_and_left_out() {
+    // well formed rows, only the first data byte differs
+    let faults = vec![(4, SliceFault::FlipByte(16))];
+    let retriever = Retriever::new(faults);
+
+    assert_eq!(retriever.retrieve().await.unwrap(), padded_blob());
+    // parity rows were asked for in place of its rows
+    assert!(retriever.source.calls().len() > SIGNERS);
+    assert!(retriever.calls_to(4).len() <= 2);
+    // which are not accepted
+    let accepted = retriever.source.accepted();
+    assert!(!accepted.is_empty());
+    assert!(accepted.iter().all(|(address, _)| *address != signer(4)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs the encoder params, set ZG_ENCODER_PARAMS_DIR"]
+async fn faults_combined() {
+    // 3 honest signers left, 1152 rows
+    let faults = vec![
+        (0, SliceFault::Error("unreachable".into())),
+        (2, SliceFault::Corrupt),
+        (4, SliceFault::FlipByte(16)),
+        (6, SliceFault::MissingRows(10)),
+    ];
+    let retriever = Retriever::new(faults);
+
+    assert_eq!(retriever.retrieve().await.unwrap(), padded_blob());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs the encoder params, set ZG_ENCODER_PARAMS_DIR"]
+async fn too_few_honest_rows() {
+    // the 2 signers left hold 768 rows
+    let faults = (0..6).map(|i| (i, SliceFault::Corrupt)).collect();
+    let retriever = Retriever::new(faults);
+
+    let err = retriever.retrieve().await.unwrap_err();
+    assert!(
+        matches!(
+            err,
+            RetrieveError::InsufficientRows {
+                available: 768,
+                required: 1024
+            }
+        ),
+        "{:?}",
+        err
+    );
+}
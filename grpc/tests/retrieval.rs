@@ -0,0 +1,616 @@
+//! Retrievals through the gRPC server against simulated signers and an
+//! in-memory quorum.
+//!
+//! Only the systematic rows are simulated, parity rows would need the encoder
+//! and its params. A retrieval hence succeeds only when every systematic row
+//! is served, and must fail otherwise rather than return wrong data. Faults
+//! the parity rows make up for are covered by `byzantine.rs`.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use contract_provider::{MemoryRegistry, RegistrySnapshot, SignerSnapshot};
+use ethers::types::{H160, H256};
+use grpc::{
+    disperser_proto::{
+        disperser_server::{self, DisperserServer},
+        RetrieveBlobReply, RetrieveBlobRequest,
+    },
+    file_root,
+    retriever::{retriever_client::RetrieverClient, BlobRequest},
+    run_server, BlobSource, Disperser, DisperserConfig, RetrieveError, ServiceConfig,
+};
+use signer_provider::{
+    FaultySource, RecordingSource, RetrieveParam, SignerEndpoint, SliceCall, SliceFault,
+    SliceSource,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{oneshot, RwLock},
+};
+use tonic::{
+    transport::{server::TcpIncoming, Channel, Endpoint, Server},
+    Code, Request, Response, Status,
+};
+
+const EPOCH: u64 = 1;
+const QUORUM_ID: u64 = 0;
+
+const ENCODED_SLICES: usize = 3072;
+const SYSTEMATIC_ROWS: usize = 1024;
+const SIGNERS: usize = 8;
+
+const ROW_COLUMNS: usize = 1024;
+const RAW_UNIT: usize = 31;
+const ROW_BYTES: usize = ROW_COLUMNS * RAW_UNIT;
+const SCALAR_SIZE: usize = 32;
+
+fn signer(i: usize) -> H160 {
+    H160::repeat_byte(i as u8 + 1)
+}
+
+/// Owner of a row, the signers take turns.
+fn owner(row: u32) -> H160 {
+    signer(row as usize % SIGNERS)
+}
+
+/// Shorter than the quorum capacity, the last rows only carry padding.
+const BLOB_SIZE: usize = (SYSTEMATIC_ROWS - 2) * ROW_BYTES + 1000;
+
+fn blob() -> &'static [u8] {
+    static BLOB: OnceLock<Vec<u8>> = OnceLock::new();
+    BLOB.get_or_init(|| (0..BLOB_SIZE).map(|i| (i % 255 + 1) as u8).collect())
+}
+
+/// `blob()` as decoded by the signer path, which keeps the zero padding of
+/// the encoder without storage nodes telling the blob length.
+fn padded_blob() -> &'static [u8] {
+    static BLOB: OnceLock<Vec<u8>> = OnceLock::new();
+    BLOB.get_or_init(|| {
+        let mut data = blob().to_vec();
+        data.resize(SYSTEMATIC_ROWS * ROW_BYTES, 0);
+        data
+    })
+}
+
+fn storage_root() -> &'static [u8] {
+    static ROOT: OnceLock<H256> = OnceLock::new();
+    ROOT.get_or_init(|| file_root(blob())).as_bytes()
+}
+
+fn encode_row(index: u32, data: &[u8]) -> Vec<u8> {
+    let mut slice = vec![];
+    slice.extend_from_slice(&(index as u64).to_le_bytes());
+    slice.extend_from_slice(&(ROW_COLUMNS as u64).to_le_bytes());
+    for unit in data.chunks(RAW_UNIT) {
+        let mut scalar = [0u8; SCALAR_SIZE];
+        scalar[..unit.len()].copy_from_slice(unit);
+        slice.extend_from_slice(&scalar);
+    }
+    slice
+}
+
+/// Signers serving the systematic rows of `blob()`, each only the rows it
+/// owns.
+struct SimulatedSigners {
+    rows: HashMap<u32, Vec<u8>>,
+}
+
+impl SimulatedSigners {
+    fn new() -> Self {
+        Self {
+            rows: (0..SYSTEMATIC_ROWS)
+                .map(|i| {
+                    let start = (i * ROW_BYTES).min(BLOB_SIZE);
+                    let end = (start + ROW_BYTES).min(BLOB_SIZE);
+                    (i as u32, encode_row(i as u32, &blob()[start..end]))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SliceSource for SimulatedSigners {
+    async fn get_slices(
+        &self,
+        signer: &SignerEndpoint,
+        params: Vec<RetrieveParam>,
+    ) -> Result<Vec<Vec<Vec<u8>>>> {
+        let mut res = vec![];
+        for param in params {
+            if param.storage_root != storage_root() {
+                bail!("unknown storage root");
+            }
+
+            let mut slices = vec![];
+            for row in param.row_indexes {
+                match self.rows.get(&row) {
+                    Some(slice) if owner(row) == signer.address => slices.push(slice.clone()),
+                    _ => bail!("{:?} does not store row {}", signer.address, row),
+                }
+            }
+            res.push(slices);
+        }
+
+        Ok(res)
+    }
+}
+
+/// Quorum of `ENCODED_SLICES` rows, with the details of the signers for which
+/// `registered` holds.
+fn signers(faults: Vec<(usize, SliceFault)>) -> RecordingSource<FaultySource<SimulatedSigners>> {
+    let mut signers = FaultySource::new(SimulatedSigners::new());
+    for (i, fault) in faults {
+        signers = signers.with_fault(signer(i), fault);
+    }
+    RecordingSource::new(signers)
+}
+
+fn snapshot(registered: impl Fn(usize) -> bool) -> RegistrySnapshot {
+    let quorum = (0..ENCODED_SLICES as u32).map(owner).collect();
+    RegistrySnapshot {
+        current_epoch: EPOCH,
+        encoded_slices: ENCODED_SLICES,
+        quorums: [(EPOCH, vec![quorum])].into(),
+        signers: (0..SIGNERS)
+            .filter(|i| registered(*i))
+            .map(|i| SignerSnapshot {
+                address: signer(i),
+                socket: format!("signer-{}:34000", i),
+                pk_g1: Default::default(),
+                pk_g2: Default::default(),
+            })
+            .collect(),
+    }
+}
+
+/// Stand-in disperser answering every request with `data` after `delay`.
+struct StandInDisperser {
+    data: Vec<u8>,
+    delay: Duration,
+}
+
+#[tonic::async_trait]
+impl disperser_server::Disperser for StandInDisperser {
+    async fn retrieve_blob(
+        &self,
+        _request: Request<RetrieveBlobRequest>,
+    ) -> Result<Response<RetrieveBlobReply>, Status> {
+        tokio::time::sleep(self.delay).await;
+        Ok(Response::new(RetrieveBlobReply {
+            data: self.data.clone(),
+        }))
+    }
+}
+
+impl StandInDisperser {
+    async fn serve(self) -> Disperser {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(DisperserServer::new(self))
+                .serve_with_incoming(incoming),
+        );
+
+        Disperser::new(&DisperserConfig {
+            endpoint: format!("http://{}", addr),
+            timeout: Duration::from_millis(500),
+            max_message_size: 64 * 1024 * 1024,
+        })
+        .unwrap()
+    }
+}
+
+struct Retriever {
+    client: RetrieverClient<Channel>,
+    source: Arc<RecordingSource<FaultySource<SimulatedSigners>>>,
+    ongoing_requests: Arc<AtomicU64>,
+    // the server stops once dropped
+    _stop: oneshot::Sender<()>,
+}
+
+impl Retriever {
+    async fn start(
+        snapshot: RegistrySnapshot,
+        faults: Vec<(usize, SliceFault)>,
+        config: ServiceConfig,
+    ) -> Self {
+        let source = Arc::new(signers(faults));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let retriever = grpc::Retriever::builder(
+            Arc::new(MemoryRegistry::new(snapshot).unwrap()),
+            source.clone(),
+        )
+        .build();
+        let retriever = Arc::new(retriever);
+        let ongoing_requests = Arc::new(AtomicU64::new(0));
+        let server_ongoing_requests = ongoing_requests.clone();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            run_server(
+                incoming,
+                retriever,
+                Arc::new(RwLock::new(config)),
+                server_ongoing_requests,
+                async {
+                    let _ = stop_rx.await;
+                },
+            )
+            .await
+            .map_err(|e| e.to_string())
+        });
+
+        let channel = Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        Self {
+            client: RetrieverClient::new(channel).max_decoding_message_size(64 * 1024 * 1024),
+            source,
+            ongoing_requests,
+            _stop: stop_tx,
+        }
+    }
+
+    async fn retrieve(&self) -> Result<Vec<u8>, Status> {
+        self.send(BlobRequest::new(
+            storage_root().to_vec(),
+            EPOCH,
+            Some(QUORUM_ID),
+        ))
+        .await
+    }
+
+    async fn send(&self, request: BlobRequest) -> Result<Vec<u8>, Status> {
+        let reply = self.client.clone().retrieve_blob(request).await?;
+        Ok(reply.into_inner().data)
+    }
+
+    fn calls_to(&self, i: usize) -> Vec<SliceCall> {
+        self.source
+            .calls()
+            .into_iter()
+            .filter(|call| call.signer.address == signer(i))
+            .collect()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn all_signers_honest() {
+    let retriever = Retriever::start(snapshot(|_| true), vec![], ServiceConfig::default()).await;
+
+    let data = retriever.retrieve().await.unwrap();
+    assert_eq!(data.len(), SYSTEMATIC_ROWS * ROW_BYTES);
+    assert_eq!(data, padded_blob());
+    // the systematic rows suffice
+    assert_eq!(retriever.source.calls().len(), SIGNERS);
+    // and are all accepted once verified
+    let accepted = retriever.source.accepted();
+    assert_eq!(accepted.len(), SIGNERS);
+    assert_eq!(
+        accepted.iter().map(|(_, rows)| rows.len()).sum::<usize>(),
+        SYSTEMATIC_ROWS
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fewer_rows_than_required() {
+    let faults = (0..5)
+        .map(|i| (i, SliceFault::Error("unreachable".into())))
+        .collect();
+    let retriever = Retriever::start(snapshot(|_| true), faults, ServiceConfig::default()).await;
+
+    let status = retriever.retrieve().await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    // parity rows were asked for before giving up
+    assert!(retriever.calls_to(7).len() > 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_signers() {
+    let delay = Duration::from_millis(300);
+    let faults = vec![(0, SliceFault::Delay(delay)), (3, SliceFault::Delay(delay))];
+    let retriever = Retriever::start(snapshot(|_| true), faults, ServiceConfig::default()).await;
+
+    let ts = Instant::now();
+    assert_eq!(retriever.retrieve().await.unwrap(), padded_blob());
+    assert!(ts.elapsed() >= delay);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn empty_reply() {
+    let faults = vec![(2, SliceFault::Empty)];
+    let retriever = Retriever::start(snapshot(|_| true), faults, ServiceConfig::default()).await;
+
+    let status = retriever.retrieve().await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    // not asked again for its parity rows
+    assert_eq!(retriever.calls_to(2).len(), 1);
+    assert!(retriever.calls_to(0).len() > 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missing_rows() {
+    let faults = vec![(5, SliceFault::MissingRows(1))];
+    let retriever = Retriever::start(snapshot(|_| true), faults, ServiceConfig::default()).await;
+
+    let status = retriever.retrieve().await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    // the short reply is rejected, not asked again for its parity rows
+    assert_eq!(retriever.calls_to(5).len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn corrupt_rows() {
+    let faults = vec![(4, SliceFault::Corrupt)];
+    let retriever = Retriever::start(snapshot(|_| true), faults, ServiceConfig::default()).await;
+
+    let status = retriever.retrieve().await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    // the row indexes are damaged too, so the reply is rejected as malformed
+    assert_eq!(retriever.calls_to(4).len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tampered_data_is_never_returned() {
+    // the first data byte of each row, past the row index and column count
+    let faults = vec![(4, SliceFault::FlipByte(16))];
+    let retriever = Retriever::start(snapshot(|_| true), faults, ServiceConfig::default()).await;
+
+    let status = retriever.retrieve().await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    // nor accepted, so no cache keeps the rows
+    assert!(retriever.source.accepted().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signer_missing_from_registry() {
+    let retriever = Retriever::start(snapshot(|i| i != 1), vec![], ServiceConfig::default()).await;
+
+    // its rows are unavailable, the other signers are still asked
+    let status = retriever.retrieve().await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    assert!(retriever.calls_to(1).is_empty());
+    for i in (0..SIGNERS).filter(|i| *i != 1) {
+        assert!(!retriever.calls_to(i).is_empty(), "signer {} not asked", i);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_requests() {
+    let retriever = Retriever::start(snapshot(|_| true), vec![], ServiceConfig::default()).await;
+    let request = || BlobRequest::new(storage_root().to_vec(), EPOCH, Some(QUORUM_ID));
+
+    let status = retriever
+        .send(BlobRequest {
+            storage_root: storage_root()[..31].to_vec(),
+            ..request()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let status = retriever
+        .send(BlobRequest {
+            epoch: EPOCH + 1,
+            ..request()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::OutOfRange);
+
+    let status = retriever
+        .send(BlobRequest {
+            quorum_id: QUORUM_ID + 1,
+            ..request()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::OutOfRange);
+    // the quorum id is ignored when any quorum is requested
+    let data = retriever
+        .send(BlobRequest {
+            quorum_id: QUORUM_ID + 1,
+            any_quorum: true,
+            ..request()
+        })
+        .await
+        .unwrap();
+    assert_eq!(data, padded_blob());
+
+    // rejected before any signer is asked
+    assert!(retriever.source.calls().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn admission_limit() {
+    let faults = (0..SIGNERS)
+        .map(|i| (i, SliceFault::Delay(Duration::from_millis(300))))
+        .collect();
+    let config = ServiceConfig {
+        max_ongoing_retrieve_request: 1,
+        ..Default::default()
+    };
+    let retriever = Retriever::start(snapshot(|_| true), faults, config).await;
+
+    let (first, second) = tokio::join!(retriever.retrieve(), async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        retriever.retrieve().await
+    });
+    assert_eq!(first.unwrap(), padded_blob());
+    assert_eq!(second.unwrap_err().code(), Code::ResourceExhausted);
+
+    // admitted again once the first request completed
+    assert_eq!(retriever.retrieve().await.unwrap(), padded_blob());
+    assert_eq!(retriever.ongoing_requests.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancelled_requests_release_admission() {
+    let faults = (0..SIGNERS)
+        .map(|i| (i, SliceFault::Delay(Duration::from_millis(300))))
+        .collect();
+    let config = ServiceConfig {
+        max_ongoing_retrieve_request: 1,
+        ..Default::default()
+    };
+    let retriever = Retriever::start(snapshot(|_| true), faults, config).await;
+
+    // the caller gives up while the signers are still answering
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), retriever.retrieve())
+            .await
+            .is_err()
+    );
+    let released = async {
+        while retriever.ongoing_requests.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), released)
+        .await
+        .expect("admission of the cancelled request is not released");
+
+    assert_eq!(retriever.retrieve().await.unwrap(), padded_blob());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn embedded_retriever() {
+    let registry = Arc::new(MemoryRegistry::new(snapshot(|_| true)).unwrap());
+    let retriever = grpc::Retriever::builder(registry, Arc::new(signers(vec![]))).build();
+
+    let retrieved = retriever
+        .retrieve(storage_root(), EPOCH, None)
+        .await
+        .unwrap();
+    assert_eq!(retrieved.data, padded_blob());
+    assert_eq!(
+        (retrieved.quorum_id, retrieved.source),
+        (Some(QUORUM_ID), BlobSource::Signers)
+    );
+
+    let range = retriever
+        .retrieve_range(storage_root(), EPOCH, QUORUM_ID, 100, ROW_BYTES as u64)
+        .await
+        .unwrap();
+    assert_eq!(range, blob()[100..100 + ROW_BYTES]);
+
+    let range = retriever
+        .retrieve_range(storage_root(), EPOCH, QUORUM_ID, BLOB_SIZE as u64 - 10, 10)
+        .await
+        .unwrap();
+    assert_eq!(range, blob()[BLOB_SIZE - 10..]);
+    // within the quorum capacity, the padding past the end of the blob
+    let range = retriever
+        .retrieve_range(storage_root(), EPOCH, QUORUM_ID, BLOB_SIZE as u64 - 10, 20)
+        .await
+        .unwrap();
+    assert_eq!(range, padded_blob()[BLOB_SIZE - 10..BLOB_SIZE + 10]);
+    let err = retriever
+        .retrieve_range(
+            storage_root(),
+            EPOCH,
+            QUORUM_ID,
+            padded_blob().len() as u64,
+            1,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RetrieveError::OutOfRange(_)));
+    // past u32::MAX rows, must not wrap around to the first rows
+    for (offset, length) in [
+        (0, 31744 << 32),
+        ((u32::MAX as u64 + 1) * ROW_BYTES as u64, 1),
+    ] {
+        let err = retriever
+            .retrieve_range(storage_root(), EPOCH, QUORUM_ID, offset, length)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RetrieveError::OutOfRange(_)));
+    }
+
+    retriever.deny_signers([signer(3)].into()).await;
+    let err = retriever
+        .retrieve(storage_root(), EPOCH, Some(QUORUM_ID))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RetrieveError::InsufficientRows { .. }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn disperser_replies_are_verified() {
+    let retrieve = |data: Vec<u8>, delay: Duration| async move {
+        let source = Arc::new(signers(vec![]));
+        let disperser = StandInDisperser { data, delay }.serve().await;
+        let retriever = grpc::Retriever::builder(
+            Arc::new(MemoryRegistry::new(snapshot(|_| true)).unwrap()),
+            source.clone(),
+        )
+        .disperser(disperser)
+        .build();
+        let retrieved = retriever
+            .retrieve(storage_root(), EPOCH, Some(QUORUM_ID))
+            .await
+            .unwrap();
+        let expected = match retrieved.source {
+            BlobSource::Disperser => blob(),
+            _ => padded_blob(),
+        };
+        assert_eq!(retrieved.data, expected);
+        (retrieved.source, source.calls().len())
+    };
+
+    // served without asking the signers
+    assert_eq!(
+        retrieve(blob().to_vec(), Duration::ZERO).await,
+        (BlobSource::Disperser, 0)
+    );
+
+    let mut tampered = blob().to_vec();
+    tampered[BLOB_SIZE / 2] ^= 1;
+    assert_eq!(
+        retrieve(tampered, Duration::ZERO).await,
+        (BlobSource::Signers, SIGNERS)
+    );
+
+    assert_eq!(
+        retrieve(blob().to_vec(), Duration::from_secs(2)).await,
+        (BlobSource::Signers, SIGNERS)
+    );
+
+    // zero bytes ending a blob are data, not padding
+    let mut data = blob().to_vec();
+    data.extend_from_slice(&[0u8; 10]);
+    let disperser = StandInDisperser {
+        data: data.clone(),
+        delay: Duration::ZERO,
+    }
+    .serve()
+    .await;
+    let retriever = grpc::Retriever::builder(
+        Arc::new(MemoryRegistry::new(snapshot(|_| true)).unwrap()),
+        Arc::new(signers(vec![])),
+    )
+    .disperser(disperser)
+    .build();
+    let retrieved = retriever
+        .retrieve(file_root(&data).as_bytes(), EPOCH, Some(QUORUM_ID))
+        .await
+        .unwrap();
+    assert_eq!(retrieved.source, BlobSource::Disperser);
+    assert_eq!(retrieved.data, data);
+}
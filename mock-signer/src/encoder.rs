@@ -0,0 +1,54 @@
+//! Encodes blobs locally into the rows a DA signer stores.
+
+use anyhow::{anyhow, Result};
+use ark_serialize::CanonicalSerialize;
+use zg_encoder::{EncodedBlob, RawBlob, RawData, ZgEncoderParams};
+
+/// Rows of an encoded blob, a third of them systematic.
+pub const ENCODED_ROWS: usize = 3 * 1024;
+
+/// A blob encoded as the DA encoder does.
+pub struct EncodedRows {
+    /// Storage root the encoder computed for the blob.
+    pub storage_root: [u8; 32],
+    /// Every row, serialized as signers return it, without the length prefix.
+    pub rows: Vec<Vec<u8>>,
+}
+
+pub struct Encoder {
+    params: ZgEncoderParams,
+}
+
+impl Encoder {
+    /// Loads the AMT params from `params_dir`, the same ones the DA encoder
+    /// uses.
+    pub fn new(params_dir: &str) -> Self {
+        Self {
+            params: ZgEncoderParams::from_dir_mont(params_dir, true, None),
+        }
+    }
+
+    /// Encodes `data` and serializes every row as signers return it.
+    pub fn encode(&self, data: &[u8]) -> Result<EncodedRows> {
+        let raw_data: RawData = data
+            .try_into()
+            .map_err(|e| anyhow!("invalid blob: {:?}", e))?;
+        let raw_blob: RawBlob = raw_data.into();
+        let encoded = EncodedBlob::build(&raw_blob, &self.params);
+
+        let mut rows = Vec::with_capacity(ENCODED_ROWS);
+        for index in 0..ENCODED_ROWS {
+            let mut row = vec![];
+            encoded
+                .get_row(index)
+                .serialize_uncompressed(&mut row)
+                .map_err(|e| anyhow!("failed to serialize row {}: {:?}", index, e))?;
+            rows.push(row);
+        }
+
+        Ok(EncodedRows {
+            storage_root: encoded.get_file_root().into(),
+            rows,
+        })
+    }
+}
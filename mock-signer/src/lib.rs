@@ -0,0 +1,262 @@
+#[macro_use]
+extern crate tracing;
+
+pub mod encoder;
+
+use std::{collections::HashMap, fs, future::Future, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use signer_provider::signer::{
+    signer_server::{Signer, SignerServer},
+    BatchRetrieveReply, BatchRetrieveRequest, BatchSignReply, BatchSignRequest, Slices,
+};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{transport::Server, Code, Request, Response, Status};
+
+const MESSAGE_SIZE_LIMIT: usize = 1024 * 1024 * 1024; // 1G
+
+/// Leading bytes of a row holding its index and column count, left intact by
+/// `corrupt_slices`.
+const ROW_HEADER_SIZE: usize = 16;
+
+/// Serialized rows of each blob by storage root, indexed by row.
+pub type BlobStore = Arc<HashMap<Vec<u8>, Vec<Vec<u8>>>>;
+
+/// Encodes the blob of each file, served under the storage root the encoder
+/// computes for it.
+pub fn load_blobs(encoder: &encoder::Encoder, files: &[String]) -> Result<BlobStore> {
+    let mut blobs = HashMap::new();
+    for file in files {
+        let data = fs::read(file).with_context(|| format!("failed to read blob {}", file))?;
+        let encoded = encoder.encode(&data)?;
+        info!(
+            storage_root = %format!("0x{}", hex::encode(encoded.storage_root)),
+            file = %file,
+            "blob encoded"
+        );
+        blobs.insert(encoded.storage_root.to_vec(), encoded.rows);
+    }
+    Ok(Arc::new(blobs))
+}
+
+/// Misbehaviour of a signer instance.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Faults {
+    /// Delay before answering a request.
+    pub latency_ms: u64,
+    /// Do not listen at all, so connections are refused.
+    pub refuse_connections: bool,
+    /// Answer without any slice.
+    pub empty_reply: bool,
+    /// Cut every slice to half its length, the length prefix unchanged.
+    pub truncate_slices: bool,
+    /// Flip every byte of the row data.
+    pub corrupt_slices: bool,
+    /// Leave out the last rows of a reply.
+    pub missing_rows: usize,
+    /// Repeat the last row of a reply.
+    pub extra_rows: usize,
+}
+
+/// Signer serving the rows of locally encoded blobs to any epoch and quorum,
+/// as requested.
+#[derive(Clone)]
+pub struct MockSigner {
+    blobs: BlobStore,
+    faults: Faults,
+}
+
+impl MockSigner {
+    pub fn new(blobs: BlobStore, faults: Faults) -> Self {
+        Self { blobs, faults }
+    }
+
+    /// Serves on `listener` until `shutdown` resolves. With
+    /// `refuse_connections` the listener is closed right away.
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<()> {
+        let addr = listener.local_addr()?;
+        if self.faults.refuse_connections {
+            info!(%addr, "mock signer refusing connections");
+            drop(listener);
+            shutdown.await;
+            return Ok(());
+        }
+
+        info!(%addr, faults = ?self.faults, "mock signer listening");
+        Server::builder()
+            .add_service(
+                SignerServer::new(self)
+                    .max_decoding_message_size(MESSAGE_SIZE_LIMIT)
+                    .max_encoding_message_size(MESSAGE_SIZE_LIMIT),
+            )
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+            .await?;
+        Ok(())
+    }
+
+    fn get_slices(&self, storage_root: &[u8], row_indexes: &[u32]) -> Result<Slices, Status> {
+        let rows = self.blobs.get(storage_root).ok_or(Status::new(
+            Code::NotFound,
+            format!("unknown storage root {}", hex::encode(storage_root)),
+        ))?;
+
+        let mut slices = vec![];
+        for index in row_indexes {
+            let row = rows.get(*index as usize).ok_or(Status::new(
+                Code::OutOfRange,
+                format!("row {} out of {}", index, rows.len()),
+            ))?;
+            slices.push(self.faulty_slice(row));
+        }
+
+        let keep = slices.len().saturating_sub(self.faults.missing_rows);
+        slices.truncate(keep);
+        if let Some(last) = slices.last().cloned() {
+            slices.extend(std::iter::repeat(last).take(self.faults.extra_rows));
+        }
+
+        Ok(Slices {
+            encoded_slice: slices,
+        })
+    }
+
+    /// Length prefixed row, with the configured damage applied.
+    fn faulty_slice(&self, row: &[u8]) -> Vec<u8> {
+        let mut slice = Vec::with_capacity(8 + row.len());
+        slice.extend_from_slice(&(row.len() as u64).to_le_bytes());
+        slice.extend_from_slice(row);
+
+        if self.faults.corrupt_slices {
+            slice
+                .iter_mut()
+                .skip(8 + ROW_HEADER_SIZE)
+                .for_each(|b| *b = !*b);
+        }
+        if self.faults.truncate_slices {
+            slice.truncate(8 + row.len() / 2);
+        }
+
+        slice
+    }
+}
+
+#[tonic::async_trait]
+impl Signer for MockSigner {
+    async fn batch_sign(
+        &self,
+        _request: Request<BatchSignRequest>,
+    ) -> Result<Response<BatchSignReply>, Status> {
+        Err(Status::new(
+            Code::Unimplemented,
+            "mock signer does not sign",
+        ))
+    }
+
+    async fn batch_retrieve(
+        &self,
+        request: Request<BatchRetrieveRequest>,
+    ) -> Result<Response<BatchRetrieveReply>, Status> {
+        if self.faults.latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(self.faults.latency_ms)).await;
+        }
+
+        let message = request.into_inner();
+        debug!(requests = message.requests.len(), "batch retrieve");
+        if self.faults.empty_reply {
+            return Ok(Response::new(BatchRetrieveReply {
+                encoded_slice: vec![],
+            }));
+        }
+
+        let mut encoded_slice = vec![];
+        for request in message.requests.iter() {
+            encoded_slice.push(self.get_slices(&request.storage_root, &request.row_indexes)?);
+        }
+
+        Ok(Response::new(BatchRetrieveReply { encoded_slice }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(faults: Faults) -> MockSigner {
+        let rows = (0..4u8).map(|i| vec![i; 32]).collect();
+        let blobs = Arc::new(HashMap::from([(vec![1u8; 32], rows)]));
+        MockSigner::new(blobs, faults)
+    }
+
+    #[test]
+    fn slices_are_length_prefixed_rows() {
+        let slices = signer(Faults::default())
+            .get_slices(&[1u8; 32], &[2, 3])
+            .unwrap()
+            .encoded_slice;
+
+        assert_eq!(slices.len(), 2);
+        assert_eq!(slices[0][..8], 32u64.to_le_bytes());
+        assert_eq!(slices[0][8..], [2u8; 32]);
+        assert_eq!(slices[1][8..], [3u8; 32]);
+    }
+
+    #[test]
+    fn faults_damage_slices() {
+        let slices = signer(Faults {
+            truncate_slices: true,
+            corrupt_slices: true,
+            missing_rows: 1,
+            ..Default::default()
+        })
+        .get_slices(&[1u8; 32], &[2, 3])
+        .unwrap()
+        .encoded_slice;
+
+        assert_eq!(slices.len(), 1);
+        // the prefix still claims the whole row
+        assert_eq!(slices[0][..8], 32u64.to_le_bytes());
+        assert_eq!(slices[0].len(), 8 + 16);
+        assert_eq!(slices[0][8..8 + ROW_HEADER_SIZE], [2u8; 16]);
+
+        let slices = signer(Faults {
+            extra_rows: 2,
+            ..Default::default()
+        })
+        .get_slices(&[1u8; 32], &[0])
+        .unwrap()
+        .encoded_slice;
+        assert_eq!(slices.len(), 3);
+    }
+
+    #[test]
+    #[ignore = "needs the encoder params, set ZG_ENCODER_PARAMS_DIR"]
+    fn blobs_are_served_under_the_encoder_root() {
+        let params_dir = std::env::var("ZG_ENCODER_PARAMS_DIR").unwrap();
+        let encoder = encoder::Encoder::new(&params_dir);
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let file = std::env::temp_dir().join(format!("mock-signer-{}.bin", std::process::id()));
+        fs::write(&file, &data).unwrap();
+
+        let blobs = load_blobs(&encoder, &[file.to_string_lossy().into_owned()]).unwrap();
+        fs::remove_file(&file).unwrap();
+        let encoded = encoder.encode(&data).unwrap();
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(blobs[&encoded.storage_root.to_vec()], encoded.rows);
+
+        let slices = MockSigner::new(blobs, Faults::default())
+            .get_slices(&encoded.storage_root, &[0, 1024])
+            .unwrap()
+            .encoded_slice;
+        // each row leads with its index
+        assert_eq!(slices[0][8..16], 0u64.to_le_bytes());
+        assert_eq!(slices[1][8..16], 1024u64.to_le_bytes());
+        assert_eq!(slices[1][8..], encoded.rows[1024]);
+    }
+}
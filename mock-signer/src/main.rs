@@ -0,0 +1,81 @@
+#[macro_use]
+extern crate tracing;
+
+use std::fs;
+
+use anyhow::{anyhow, Result};
+use clap::{arg, command};
+use mock_signer::{encoder::Encoder, load_blobs, Faults, MockSigner};
+use serde::Deserialize;
+use tokio::{net::TcpListener, sync::watch};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Directory of the AMT params of the encoder.
+    params_dir: String,
+    blobs: Vec<BlobConfig>,
+    instances: Vec<InstanceConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlobConfig {
+    /// Blob content, served under the storage root computed from it.
+    file: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceConfig {
+    listen_address: String,
+    #[serde(default)]
+    faults: Faults,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let matches = command!()
+        .arg(arg!(-c --config <FILE> "Sets the mock signer config file").required(true))
+        .get_matches();
+    let config_file = matches.get_one::<String>("config").expect("required");
+    let config: Config = toml::from_str(&fs::read_to_string(config_file)?)
+        .map_err(|e| anyhow!("Cannot parse config: {}", e))?;
+
+    let encoder = Encoder::new(&config.params_dir);
+    let files: Vec<_> = config.blobs.into_iter().map(|blob| blob.file).collect();
+    let blobs = load_blobs(&encoder, &files)?;
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let mut tasks = vec![];
+    for instance in config.instances {
+        let listener = TcpListener::bind(&instance.listen_address)
+            .await
+            .map_err(|e| anyhow!("failed to bind {}: {}", instance.listen_address, e))?;
+        let mut shutdown_rx = shutdown_rx.clone();
+        let signer = MockSigner::new(blobs.clone(), instance.faults);
+        tasks.push(tokio::spawn(async move {
+            signer
+                .serve(listener, async move {
+                    let _ = shutdown_rx.changed().await;
+                })
+                .await
+        }));
+    }
+
+    tokio::signal::ctrl_c().await?;
+    info!("stopping mock signers");
+    let _ = shutdown_tx.send(());
+    for task in tasks {
+        task.await??;
+    }
+
+    Ok(())
+}
@@ -1,59 +1,403 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    str::FromStr,
+    time::Duration,
+};
+
 use anyhow::{anyhow, bail, Result};
-use config::ConfigError::NotFound;
+use contract_provider::EthRpcConfig;
+use ethers::types::H160;
+use grpc::{DisperserConfig, ServiceConfig, StorageNodeConfig};
+use serde::{Deserialize, Serialize};
+use signer_provider::SignerConfig;
+use tracing::Level;
+
+use crate::logging::{self, LogFormat, LogRotation};
+
+/// Prefix of the environment variables overriding config keys, e.g.
+/// `RETRIEVER_LOG_LEVEL` overrides `log_level`.
+const ENV_PREFIX: &str = "RETRIEVER";
 
 mod cli {
     use clap::{arg, command, Command};
 
     pub fn cli_app() -> Command {
         command!()
-            .arg(arg!(-c --config <FILE> "Sets a custom config file"))
-            .allow_external_subcommands(true)
+            .arg(arg!(-c --config <FILE> "Sets a custom config file").global(true))
+            .subcommand(
+                Command::new("config")
+                    .about("Inspects the configuration")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("check")
+                            .about("Validates the config file and prints the resolved config"),
+                    ),
+            )
     }
 }
 
-struct RawConfig(config::Config);
+pub enum CliCommand {
+    Run,
+    ConfigCheck,
+}
 
-impl RawConfig {
-    fn get_string(&self, key: &'static str) -> Result<String> {
-        self.0
-            .get_string(key)
-            .map_err(|e| anyhow!("Cannot parse config key `{}` as string: {:?}", key, e))
-    }
+pub struct Cli {
+    pub config_file: String,
+    pub command: CliCommand,
+}
 
-    fn get_u64_opt(&self, key: &'static str) -> Result<Option<u64>> {
-        match self.0.get_int(key) {
-            Ok(x) => Ok(Some(x as u64)),
-            Err(NotFound(_)) => Ok(None),
-            Err(e) => Err(anyhow!("Cannot parse config key `{}` as int: {:?}", key, e)),
-        }
+impl Cli {
+    pub fn parse() -> Result<Self> {
+        let matches = cli::cli_app().get_matches();
+
+        let command = match matches.subcommand() {
+            Some(("config", sub)) => match sub.subcommand() {
+                Some(("check", _)) => CliCommand::ConfigCheck,
+                _ => unreachable!("subcommand is required"),
+            },
+            _ => CliCommand::Run,
+        };
+
+        let config_file = match matches.get_one::<String>("config") {
+            Some(x) => x.clone(),
+            None => bail!(anyhow!("Config file missing!")),
+        };
+
+        Ok(Self {
+            config_file,
+            command,
+        })
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
+    // logging
     pub log_level: String,
-    pub eth_rpc_url: String,
+    pub log_filters: Vec<String>,
+    pub log_format: LogFormat,
+    pub log_file_dir: Option<String>,
+    pub log_file_prefix: String,
+    pub log_file_rotation: LogRotation,
+    pub log_file_max_files: usize,
+
+    // tracing
+    pub otlp_endpoint: Option<String>,
+    pub otlp_service_name: String,
+    pub otlp_sample_ratio: f64,
+
+    // listeners
     pub grpc_listen_address: String,
-    pub max_ongoing_retrieve_request: Option<u64>,
+    pub metrics_listen_address: Option<String>,
+
+    // eth rpc
+    pub eth_rpc_endpoint: Option<String>,
+    pub eth_rpc_endpoints: Vec<String>,
+    pub eth_rpc_timeout_ms: u64,
+    pub eth_rpc_retries: usize,
+    pub eth_rpc_retry_backoff_ms: u64,
+    pub eth_rpc_rate_limit_retries: usize,
+    pub eth_rpc_cooldown_ms: u64,
+    pub eth_rpc_health_check_interval_ms: u64,
+    pub eth_rpc_cross_check_quorum: bool,
+    pub chain_params_cache_ttl_secs: u64,
+    pub registry_snapshot: Option<String>,
+
+    // signers
+    pub signer_connect_timeout_ms: u64,
+    pub signer_request_timeout_ms: u64,
+    pub signer_deny_list: Vec<String>,
+
+    // disperser
+    pub disperser_endpoint: Option<String>,
+    pub disperser_timeout_ms: u64,
+
+    // storage nodes
+    pub storage_node_urls: Vec<String>,
+    pub storage_node_timeout_ms: u64,
+
+    // limits
+    pub max_ongoing_retrieve_request: u64,
+    pub rate_limit_per_sec: u64,
+    pub rate_limit_burst: u64,
+    pub max_message_size: usize,
+
+    // shutdown
+    pub shutdown_grace_period_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let eth_rpc = EthRpcConfig::new(vec![]);
+        let signer = SignerConfig::default();
+        let service = ServiceConfig::default();
+
+        Self {
+            log_level: "info".into(),
+            log_filters: vec![],
+            log_format: LogFormat::Text,
+            log_file_dir: None,
+            log_file_prefix: "retriever.log".into(),
+            log_file_rotation: LogRotation::Daily,
+            log_file_max_files: 0,
+            otlp_endpoint: None,
+            otlp_service_name: "0g-da-retriever".into(),
+            otlp_sample_ratio: 1.0,
+            grpc_listen_address: "0.0.0.0:34005".into(),
+            metrics_listen_address: None,
+            eth_rpc_endpoint: None,
+            eth_rpc_endpoints: vec![],
+            eth_rpc_timeout_ms: eth_rpc.request_timeout.as_millis() as u64,
+            eth_rpc_retries: eth_rpc.retries,
+            eth_rpc_retry_backoff_ms: eth_rpc.retry_backoff.as_millis() as u64,
+            eth_rpc_rate_limit_retries: eth_rpc.rate_limit_retries,
+            eth_rpc_cooldown_ms: eth_rpc.cooldown.as_millis() as u64,
+            eth_rpc_health_check_interval_ms: eth_rpc.health_check_interval.as_millis() as u64,
+            eth_rpc_cross_check_quorum: eth_rpc.cross_check_quorum,
+            chain_params_cache_ttl_secs: eth_rpc.params_cache_ttl.as_secs(),
+            registry_snapshot: None,
+            signer_connect_timeout_ms: signer.connect_timeout.as_millis() as u64,
+            signer_request_timeout_ms: signer.request_timeout.as_millis() as u64,
+            signer_deny_list: vec![],
+            disperser_endpoint: None,
+            disperser_timeout_ms: 5000,
+            storage_node_urls: vec![],
+            storage_node_timeout_ms: 30000,
+            max_ongoing_retrieve_request: service.max_ongoing_retrieve_request,
+            rate_limit_per_sec: service.rate_limit_per_sec,
+            rate_limit_burst: service.rate_limit_burst,
+            max_message_size: service.max_message_size,
+            shutdown_grace_period_secs: 30,
+        }
+    }
 }
 
 impl Config {
-    pub fn from_cli_file() -> Result<Self> {
-        let matches = cli::cli_app().get_matches();
-        let c = if let Some(config_file) = matches.get_one::<String>("config") {
-            RawConfig(
-                config::Config::builder()
-                    .add_source(config::File::with_name(&config_file))
-                    .build()?,
+    /// Loads the config file, applies environment overrides and validates
+    /// the result.
+    pub fn load(config_file: &str) -> Result<Self> {
+        let config: Config = config::Config::builder()
+            .add_source(config::File::with_name(config_file))
+            .add_source(
+                config::Environment::with_prefix(ENV_PREFIX)
+                    .source(Some(Self::env_overrides()?))
+                    .try_parsing(true)
+                    .list_separator(",")
+                    .with_list_parse_key("log_filters")
+                    .with_list_parse_key("eth_rpc_endpoints")
+                    .with_list_parse_key("signer_deny_list")
+                    .with_list_parse_key("storage_node_urls"),
             )
-        } else {
-            bail!(anyhow!("Config file missing!"));
+            .build()?
+            .try_deserialize()
+            .map_err(|e| anyhow!("Cannot parse config: {}", e))?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Environment variables named after a config field. Other variables
+    /// sharing the prefix belong to someone else and are left out, as unknown
+    /// fields are rejected.
+    fn env_overrides() -> Result<HashMap<String, String>> {
+        let fields = match serde_json::to_value(Config::default())? {
+            serde_json::Value::Object(fields) => fields,
+            _ => bail!("config does not serialize to a map"),
         };
+        let prefix = format!("{}_", ENV_PREFIX).to_lowercase();
 
-        Ok(Self {
-            log_level: c.get_string("log_level")?,
-            eth_rpc_url: c.get_string("eth_rpc_endpoint")?,
-            grpc_listen_address: c.get_string("grpc_listen_address")?,
-            max_ongoing_retrieve_request: c.get_u64_opt("max_ongoing_retrieve_request")?,
+        Ok(std::env::vars()
+            .filter(|(key, _)| {
+                key.to_lowercase()
+                    .strip_prefix(&prefix)
+                    .map_or(false, |field| fields.contains_key(field))
+            })
+            .collect())
+    }
+
+    fn validate(&self) -> Result<()> {
+        Level::from_str(&self.log_level)
+            .map_err(|e| anyhow!("Invalid `log_level` {:?}: {}", self.log_level, e))?;
+        logging::env_filter(self)?;
+        if !(0.0..=1.0).contains(&self.otlp_sample_ratio) {
+            bail!(
+                "`otlp_sample_ratio` must be within [0, 1], got {}",
+                self.otlp_sample_ratio
+            );
+        }
+
+        SocketAddr::from_str(&self.grpc_listen_address).map_err(|e| {
+            anyhow!(
+                "Invalid `grpc_listen_address` {:?}: {}",
+                self.grpc_listen_address,
+                e
+            )
+        })?;
+        if let Some(addr) = &self.metrics_listen_address {
+            SocketAddr::from_str(addr)
+                .map_err(|e| anyhow!("Invalid `metrics_listen_address` {:?}: {}", addr, e))?;
+        }
+
+        let urls = self.eth_rpc_urls();
+        if urls.is_empty() && self.registry_snapshot.is_none() {
+            bail!(
+                "Either `eth_rpc_endpoint`, `eth_rpc_endpoints` or `registry_snapshot` must be set"
+            );
+        }
+        if self.eth_rpc_cross_check_quorum && urls.len() < 2 {
+            bail!("`eth_rpc_cross_check_quorum` requires at least two eth rpc endpoints");
+        }
+
+        for address in self.signer_deny_list.iter() {
+            H160::from_str(address).map_err(|e| {
+                anyhow!("Invalid signer {:?} in `signer_deny_list`: {}", address, e)
+            })?;
+        }
+
+        if let Some(endpoint) = &self.disperser_endpoint {
+            tonic::transport::Endpoint::from_shared(endpoint.clone())
+                .map_err(|e| anyhow!("Invalid `disperser_endpoint` {:?}: {}", endpoint, e))?;
+        }
+
+        for (key, value) in [
+            ("eth_rpc_timeout_ms", self.eth_rpc_timeout_ms),
+            (
+                "eth_rpc_health_check_interval_ms",
+                self.eth_rpc_health_check_interval_ms,
+            ),
+            ("signer_connect_timeout_ms", self.signer_connect_timeout_ms),
+            ("signer_request_timeout_ms", self.signer_request_timeout_ms),
+            ("disperser_timeout_ms", self.disperser_timeout_ms),
+            ("storage_node_timeout_ms", self.storage_node_timeout_ms),
+            (
+                "max_ongoing_retrieve_request",
+                self.max_ongoing_retrieve_request,
+            ),
+            ("max_message_size", self.max_message_size as u64),
+        ] {
+            if value == 0 {
+                bail!("`{}` must be positive", key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keys changed in `other` that only take effect after a restart.
+    pub fn restart_required_changes(&self, other: &Config) -> Vec<&'static str> {
+        let mut changes = vec![];
+        if self.log_format != other.log_format
+            || self.log_file_dir != other.log_file_dir
+            || self.log_file_prefix != other.log_file_prefix
+            || self.log_file_rotation != other.log_file_rotation
+            || self.log_file_max_files != other.log_file_max_files
+        {
+            changes.push("log_format, log_file_*");
+        }
+        if self.otlp_endpoint != other.otlp_endpoint
+            || self.otlp_service_name != other.otlp_service_name
+            || self.otlp_sample_ratio != other.otlp_sample_ratio
+        {
+            changes.push("otlp_*");
+        }
+        if self.grpc_listen_address != other.grpc_listen_address {
+            changes.push("grpc_listen_address");
+        }
+        if self.metrics_listen_address != other.metrics_listen_address {
+            changes.push("metrics_listen_address");
+        }
+        if self.eth_rpc_config() != other.eth_rpc_config() {
+            changes.push("eth_rpc_*");
+        }
+        if self.registry_snapshot != other.registry_snapshot {
+            changes.push("registry_snapshot");
+        }
+        if self.disperser_config() != other.disperser_config() {
+            changes.push("disperser_*");
+        }
+        if self.storage_node_config() != other.storage_node_config() {
+            changes.push("storage_node_*");
+        }
+        // signer connections pick up a new size at once
+        if self.max_message_size != other.max_message_size {
+            changes.push("max_message_size of the grpc server and disperser");
+        }
+        changes
+    }
+
+    /// Resolved config in TOML, as printed by `config check`.
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    fn eth_rpc_urls(&self) -> Vec<String> {
+        self.eth_rpc_endpoint
+            .iter()
+            .chain(self.eth_rpc_endpoints.iter())
+            .cloned()
+            .collect()
+    }
+
+    pub fn eth_rpc_config(&self) -> EthRpcConfig {
+        EthRpcConfig {
+            urls: self.eth_rpc_urls(),
+            request_timeout: Duration::from_millis(self.eth_rpc_timeout_ms),
+            cooldown: Duration::from_millis(self.eth_rpc_cooldown_ms),
+            health_check_interval: Duration::from_millis(self.eth_rpc_health_check_interval_ms),
+            retries: self.eth_rpc_retries,
+            retry_backoff: Duration::from_millis(self.eth_rpc_retry_backoff_ms),
+            rate_limit_retries: self.eth_rpc_rate_limit_retries,
+            cross_check_quorum: self.eth_rpc_cross_check_quorum,
+            params_cache_ttl: Duration::from_secs(self.chain_params_cache_ttl_secs),
+        }
+    }
+
+    pub fn signer_config(&self) -> SignerConfig {
+        SignerConfig {
+            connect_timeout: Duration::from_millis(self.signer_connect_timeout_ms),
+            request_timeout: Duration::from_millis(self.signer_request_timeout_ms),
+            max_message_size: self.max_message_size,
+        }
+    }
+
+    pub fn disperser_config(&self) -> Option<DisperserConfig> {
+        self.disperser_endpoint
+            .as_ref()
+            .map(|endpoint| DisperserConfig {
+                endpoint: endpoint.clone(),
+                timeout: Duration::from_millis(self.disperser_timeout_ms),
+                max_message_size: self.max_message_size,
+            })
+    }
+
+    pub fn storage_node_config(&self) -> Option<StorageNodeConfig> {
+        if self.storage_node_urls.is_empty() {
+            return None;
+        }
+
+        Some(StorageNodeConfig {
+            urls: self.storage_node_urls.clone(),
+            timeout: Duration::from_millis(self.storage_node_timeout_ms),
         })
     }
+
+    pub fn service_config(&self) -> ServiceConfig {
+        ServiceConfig {
+            max_ongoing_retrieve_request: self.max_ongoing_retrieve_request,
+            rate_limit_per_sec: self.rate_limit_per_sec,
+            rate_limit_burst: self.rate_limit_burst,
+            max_message_size: self.max_message_size,
+        }
+    }
+
+    /// Signers never asked for slices.
+    pub fn denied_signers(&self) -> HashSet<H160> {
+        // validated on load
+        self.signer_deny_list
+            .iter()
+            .filter_map(|address| H160::from_str(address).ok())
+            .collect()
+    }
 }
@@ -0,0 +1,180 @@
+use anyhow::{anyhow, Result};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{self as sdktrace, Sampler},
+    Resource,
+};
+use serde::{Deserialize, Serialize};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{fmt::MakeWriter, prelude::*, reload, EnvFilter, Layer, Registry};
+
+use crate::config::Config;
+
+pub type LogHandle = reload::Handle<EnvFilter, Registry>;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync + 'static>;
+
+/// Spans exported over OTLP, whatever the log level. The retrieval spans are
+/// all at info level.
+const OTLP_FILTER: &str = "info";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, span fields such as `request_id` and
+    /// `signer` included.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+/// Installs the global subscriber: stdout, plus a rotating file if
+/// `log_file_dir` is set and an OTLP span exporter if `otlp_endpoint` is set.
+/// The filter of the log outputs can be swapped through the returned handle,
+/// the exporter has its own; the guard flushes the file writer when dropped.
+///
+/// Must be called within a tokio runtime, the span exporter runs on it.
+pub fn init(config: &Config) -> Result<(LogHandle, Option<WorkerGuard>)> {
+    let (filter, handle) = reload::Layer::new(env_filter(config)?);
+
+    let mut outputs = vec![fmt_layer(config.log_format, std::io::stdout, true)];
+
+    let mut guard = None;
+    if let Some(dir) = config.log_file_dir.as_ref() {
+        let mut builder = RollingFileAppender::builder()
+            .rotation(config.log_file_rotation.into())
+            .filename_prefix(&config.log_file_prefix);
+        // zero keeps every file
+        if config.log_file_max_files > 0 {
+            builder = builder.max_log_files(config.log_file_max_files);
+        }
+        let appender = builder
+            .build(dir)
+            .map_err(|e| anyhow!("Cannot open log file in {:?}: {}", dir, e))?;
+        let (writer, worker_guard) = tracing_appender::non_blocking(appender);
+        outputs.push(fmt_layer(config.log_format, writer, false));
+        guard = Some(worker_guard);
+    }
+    let mut layers = vec![outputs.with_filter(filter).boxed()];
+
+    // W3C trace context, extracted from incoming calls and injected into
+    // signer calls
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    if let Some(endpoint) = config.otlp_endpoint.as_ref() {
+        let tracer = otlp_tracer(config, endpoint)?;
+        // not subject to `log_level`, which would drop the spans below it
+        layers.push(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(EnvFilter::new(OTLP_FILTER))
+                .boxed(),
+        );
+    }
+
+    tracing_subscriber::registry().with(layers).try_init()?;
+
+    Ok((handle, guard))
+}
+
+/// Filter of `log_level` as the default, refined by the `log_filters`
+/// directives, e.g. `grpc=debug` or `ethers_providers=warn`.
+pub fn env_filter(config: &Config) -> Result<EnvFilter> {
+    let directives = std::iter::once(config.log_level.as_str())
+        .chain(config.log_filters.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    EnvFilter::builder()
+        .parse(&directives)
+        .map_err(|e| anyhow!("Invalid log filter {:?}: {}", directives, e))
+}
+
+/// Flushes the spans not exported yet.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+fn otlp_tracer(config: &Config, endpoint: &str) -> Result<sdktrace::Tracer> {
+    // a sampled caller keeps the whole trace
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+        config.otlp_sample_ratio,
+    )));
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            sdktrace::config()
+                .with_sampler(sampler)
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    config.otlp_service_name.clone(),
+                )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map_err(|e| anyhow!("Cannot set up OTLP exporter to {:?}: {}", endpoint, e))
+}
+
+fn fmt_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().with_span_list(true).boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_filter_combines_level_and_directives() {
+        let mut config = Config {
+            log_level: "warn".into(),
+            log_filters: vec!["grpc=debug".into(), "contract_provider=info".into()],
+            ..Default::default()
+        };
+        let filter = env_filter(&config).unwrap().to_string();
+        assert!(filter.contains("grpc=debug"));
+        assert!(filter.contains("warn"));
+
+        config.log_filters.push("grpc=loud".into());
+        assert!(env_filter(&config).is_err());
+    }
+}
@@ -2,80 +2,282 @@
 extern crate tracing;
 
 mod config;
+mod logging;
+mod reload;
 mod runtime;
 
-use std::{error::Error, net::SocketAddr, str::FromStr, sync::Arc};
+use std::{
+    net::{SocketAddr, TcpListener},
+    process::ExitCode,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::{anyhow, Result};
-use config::Config;
-use contract_provider::ContractProvider;
-use grpc::run_server;
-use runtime::{make_environment, Environment};
+use config::{Cli, CliCommand, Config};
+use contract_provider::{ContractProvider, MemoryRegistry, SignerRegistry};
+use grpc::{run_metrics_server, run_server, Disperser, Retriever, ServiceConfig, StorageNodes};
+use logging::LogHandle;
+use reload::Reloader;
+use runtime::{make_environment, spawn_supervised, Environment};
 use signer_provider::SignerProvider;
-use task_executor::TaskExecutor;
-use tracing::Level;
+use task_executor::{ShutdownReason, TaskExecutor};
+use tokio::{
+    sync::{oneshot, RwLock},
+    task::{AbortHandle, JoinHandle},
+};
+use tonic::transport::server::TcpIncoming;
 
-fn main() -> Result<(), Box<dyn Error>> {
+/// Invalid command line or config file.
+const EXIT_CODE_CONFIG: u8 = 2;
+/// Failed to start, e.g. listen address in use or eth rpc unreachable.
+const EXIT_CODE_STARTUP: u8 = 3;
+/// A supervised task failed while running.
+const EXIT_CODE_FAILURE: u8 = 4;
+
+fn main() -> ExitCode {
     // enable backtraces
     std::env::set_var("RUST_BACKTRACE", "1");
 
-    let (environment, runtime, executor) = make_environment().unwrap();
-
-    let res = runtime.block_on(async { async_main(environment, executor).await });
+    // CLI, config
+    let (cli, config) = match Cli::parse().and_then(|cli| {
+        let config = Config::load(&cli.config_file)?;
+        Ok((cli, config))
+    }) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Invalid config: {:#}", e);
+            return ExitCode::from(EXIT_CODE_CONFIG);
+        }
+    };
 
-    if let Err(e) = res {
-        error!(reason =?e, "Service exit");
+    if let CliCommand::ConfigCheck = cli.command {
+        eprintln!("config file {:?} is valid", cli.config_file);
+        match config.to_toml() {
+            Ok(x) => print!("{}", x),
+            Err(e) => {
+                eprintln!("Failed to print config: {:#}", e);
+                return ExitCode::from(EXIT_CODE_CONFIG);
+            }
+        }
+        return ExitCode::SUCCESS;
     }
 
-    runtime.shutdown_timeout(std::time::Duration::from_secs(15));
+    let (environment, runtime, executor) = match make_environment() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_CODE_STARTUP);
+        }
+    };
+
+    // tracing, the filter is reloadable; the guard flushes the log file on exit
+    let (log_handle, _log_guard) = match runtime.block_on(async { logging::init(&config) }) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Failed to set up logging: {:#}", e);
+            return ExitCode::from(EXIT_CODE_STARTUP);
+        }
+    };
+
+    let res = runtime.block_on(async {
+        async_main(environment, executor, cli.config_file, config, log_handle).await
+    });
+
+    let exit_code = match res {
+        Ok(None) | Ok(Some(ShutdownReason::Success(_))) => ExitCode::SUCCESS,
+        Ok(Some(ShutdownReason::Failure(reason))) => {
+            error!(reason, "Service failed");
+            ExitCode::from(EXIT_CODE_FAILURE)
+        }
+        Err(e) => {
+            error!(reason = ?e, "Service exit");
+            ExitCode::from(EXIT_CODE_STARTUP)
+        }
+    };
+
     info!("Stopped");
+    logging::shutdown();
+    runtime.shutdown_timeout(std::time::Duration::from_secs(15));
 
-    Ok(())
+    exit_code
 }
 
+/// Runs the service until a shutdown signal, returning the reason of an
+/// internal shutdown.
 async fn async_main(
-    environment: Environment,
+    mut environment: Environment,
     executor: TaskExecutor,
-) -> Result<(), Box<dyn Error>> {
-    // CLI, config
-    let config = Config::from_cli_file().unwrap();
+    config_file: String,
+    config: Config,
+    log_handle: LogHandle,
+) -> Result<Option<ShutdownReason>> {
+    // let ctx = Context::new(config).await?;
 
-    // tracing
-    tracing_subscriber::fmt()
-        .with_max_level(Level::from_str(&config.log_level)?)
-        // .with_ansi(false)
-        .init();
+    let service_config = Arc::new(RwLock::new(config.service_config()));
+    let signer_provider = Arc::new(SignerProvider::new(config.signer_config())?);
+    let retriever = build_retriever(executor.clone(), &config, signer_provider.clone()).await?;
 
-    // let ctx = Context::new(config).await?;
+    let grace_period = Duration::from_secs(config.shutdown_grace_period_secs);
+    let server = start_server(
+        executor.clone(),
+        &config,
+        service_config.clone(),
+        retriever.clone(),
+    )
+    .await?;
 
-    let rpc_res = start_server(executor, &config).await;
-    rpc_res?;
+    let (reload_tx, mut reload_rx) = tokio::sync::mpsc::channel(1);
+    let mut reloader = Reloader::new(
+        config_file,
+        config,
+        log_handle,
+        service_config,
+        signer_provider,
+        retriever,
+    );
+    executor.spawn(
+        async move {
+            while reload_rx.recv().await.is_some() {
+                reloader.reload().await;
+            }
+        },
+        "config_reloader",
+    );
 
-    environment.wait_shutdown_signal().await;
+    let reason = environment
+        .wait_shutdown_signal(|| {
+            // a reload already pending picks up the latest file as well
+            let _ = reload_tx.try_send(());
+        })
+        .await;
 
     info!("signal received, stopping..");
-    Ok(())
+    server.drain(grace_period).await;
+
+    // keeps the executor tasks of ongoing retrievals alive while draining
+    drop(environment);
+    Ok(reason)
 }
 
-async fn start_server(executor: TaskExecutor, cfg: &Config) -> Result<()> {
-    let grpc_listen_address = cfg.grpc_listen_address.clone();
-    let max_ongoing_retrieve_request = cfg.max_ongoing_retrieve_request;
-
-    let contract_provider = ContractProvider::new(&cfg.eth_rpc_url).await?;
-    let signer_provider = Arc::new(SignerProvider::new()?);
-
-    info!("starting grpc server at {:?}", grpc_listen_address);
-    tokio::spawn(async move {
-        run_server(
-            SocketAddr::from_str(&grpc_listen_address).unwrap(),
-            contract_provider,
-            signer_provider,
-            max_ongoing_retrieve_request,
-            executor,
-        )
-        .await
-        .map_err(|e| anyhow!(e.to_string()))
-        .unwrap();
-    });
-    Ok(())
+struct ServerHandle {
+    shutdown_tx: oneshot::Sender<()>,
+    join: JoinHandle<()>,
+    abort: AbortHandle,
+    ongoing_requests: Arc<AtomicU64>,
+}
+
+impl ServerHandle {
+    /// Stops accepting calls and waits up to `grace_period` for the ongoing
+    /// ones to finish, then aborts the server.
+    async fn drain(mut self, grace_period: Duration) {
+        let ongoing = self.ongoing_requests.load(Ordering::SeqCst);
+        info!(ongoing, ?grace_period, "draining grpc server");
+
+        let _ = self.shutdown_tx.send(());
+        match tokio::time::timeout(grace_period, &mut self.join).await {
+            Ok(_) => info!("grpc server drained"),
+            Err(_) => {
+                let cut_off = self.ongoing_requests.load(Ordering::SeqCst);
+                warn!(
+                    cut_off,
+                    "grace period elapsed, cutting off ongoing retrievals"
+                );
+                // the join handle is the supervisor's, the server itself
+                // is aborted
+                self.abort.abort();
+                let _ = self.join.await;
+            }
+        }
+    }
+}
+
+/// Builds the retriever over the configured registry, disperser and storage
+/// nodes.
+async fn build_retriever(
+    executor: TaskExecutor,
+    cfg: &Config,
+    signer_provider: Arc<SignerProvider>,
+) -> Result<Arc<Retriever>> {
+    let registry: Arc<dyn SignerRegistry> = match cfg.registry_snapshot.as_ref() {
+        Some(path) => {
+            info!("loading signer registry from {:?}", path);
+            Arc::new(MemoryRegistry::from_file(path)?)
+        }
+        None => Arc::new(ContractProvider::new(&cfg.eth_rpc_config()).await?),
+    };
+    let mut retriever = Retriever::builder(registry, signer_provider)
+        .executor(executor)
+        .denied_signers(cfg.denied_signers());
+    if let Some(config) = cfg.disperser_config() {
+        info!("asking disperser {:?} first", config.endpoint);
+        retriever = retriever.disperser(Disperser::new(&config)?);
+    }
+    if let Some(config) = cfg.storage_node_config() {
+        info!("falling back to storage nodes {:?}", config.urls);
+        retriever = retriever.storage_nodes(StorageNodes::new(&config)?);
+    }
+    Ok(Arc::new(retriever.build()))
+}
+
+/// Binds the listeners and starts the servers under supervision, so that
+/// bind errors fail the startup and later failures shut the service down.
+async fn start_server(
+    executor: TaskExecutor,
+    cfg: &Config,
+    service_config: Arc<RwLock<ServiceConfig>>,
+    retriever: Arc<Retriever>,
+) -> Result<ServerHandle> {
+    let ongoing_requests = Arc::new(AtomicU64::new(0));
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+
+    if let Some(metrics_listen_address) = cfg.metrics_listen_address.as_ref() {
+        info!("starting metrics server at {:?}", metrics_listen_address);
+        let listener = TcpListener::bind(SocketAddr::from_str(metrics_listen_address)?)
+            .map_err(|e| anyhow!("failed to bind {}: {}", metrics_listen_address, e))?;
+        spawn_supervised(
+            &executor,
+            async move {
+                run_metrics_server(listener)
+                    .await
+                    .map_err(|e| anyhow!(e.to_string()))
+            },
+            "metrics_server",
+            "metrics server failed",
+        );
+    }
+
+    info!("starting grpc server at {:?}", cfg.grpc_listen_address);
+    let incoming = TcpIncoming::new(SocketAddr::from_str(&cfg.grpc_listen_address)?, true, None)
+        .map_err(|e| anyhow!("failed to bind {}: {}", cfg.grpc_listen_address, e))?;
+    let server_ongoing_requests = ongoing_requests.clone();
+    let (join, abort) = spawn_supervised(
+        &executor,
+        async move {
+            run_server(
+                incoming,
+                retriever,
+                service_config,
+                server_ongoing_requests,
+                async {
+                    let _ = shutdown_rx.await;
+                },
+            )
+            .await
+            .map_err(|e| anyhow!(e.to_string()))
+        },
+        "grpc_server",
+        "grpc server failed",
+    );
+
+    Ok(ServerHandle {
+        shutdown_tx,
+        join,
+        abort,
+        ongoing_requests,
+    })
 }
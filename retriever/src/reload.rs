@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use grpc::{Retriever, ServiceConfig};
+use signer_provider::SignerProvider;
+use tokio::sync::RwLock;
+
+use crate::{
+    config::Config,
+    logging::{self, LogHandle},
+};
+
+/// Re-reads the config file and applies the settings that are safe to change
+/// at runtime. In-flight retrievals keep the settings they started with.
+pub struct Reloader {
+    config_file: String,
+    current: Config,
+    log_handle: LogHandle,
+    service_config: Arc<RwLock<ServiceConfig>>,
+    signer_provider: Arc<SignerProvider>,
+    retriever: Arc<Retriever>,
+}
+
+impl Reloader {
+    pub fn new(
+        config_file: String,
+        current: Config,
+        log_handle: LogHandle,
+        service_config: Arc<RwLock<ServiceConfig>>,
+        signer_provider: Arc<SignerProvider>,
+        retriever: Arc<Retriever>,
+    ) -> Self {
+        Self {
+            config_file,
+            current,
+            log_handle,
+            service_config,
+            signer_provider,
+            retriever,
+        }
+    }
+
+    pub async fn reload(&mut self) {
+        let config = match Config::load(&self.config_file) {
+            Ok(x) => x,
+            Err(e) => {
+                error!(
+                    config_file = %self.config_file,
+                    "config reload failed, keep current config, error: {:?}", e
+                );
+                return;
+            }
+        };
+
+        match logging::env_filter(&config) {
+            Ok(filter) => {
+                if let Err(e) = self.log_handle.reload(filter) {
+                    error!("failed to reload log filter, error: {:?}", e);
+                }
+            }
+            // validated on load
+            Err(e) => error!("invalid log filter, error: {:?}", e),
+        }
+        *self.service_config.write().await = config.service_config();
+        self.signer_provider.update_config(config.signer_config());
+        self.retriever.deny_signers(config.denied_signers()).await;
+
+        let ignored = self.current.restart_required_changes(&config);
+        if !ignored.is_empty() {
+            warn!(?ignored, "config changes require a restart to take effect");
+        }
+
+        info!(config_file = %self.config_file, "config reloaded");
+        self.current = config;
+    }
+}
@@ -1,3 +1,5 @@
+use std::future::Future;
+
 use exit_future::Signal;
 use futures::channel::mpsc::Receiver;
 use futures::StreamExt;
@@ -5,6 +7,7 @@ use task_executor::{ShutdownReason, TaskExecutor};
 use tokio::{
     runtime::Runtime,
     signal::unix::{signal, SignalKind},
+    task::{AbortHandle, JoinHandle},
 };
 
 pub fn make_environment() -> Result<(Environment, Runtime, TaskExecutor), String> {
@@ -18,6 +21,44 @@ pub fn make_environment() -> Result<(Environment, Runtime, TaskExecutor), String
     Ok((Environment { signal, signal_rx }, runtime, executor))
 }
 
+/// Spawns a task that must keep running for the service to be healthy. If it
+/// fails or panics, a shutdown with `failure` is requested through the
+/// executor. The returned join handle completes once the task completes, the
+/// abort handle stops the task itself without requesting a shutdown.
+pub fn spawn_supervised<F>(
+    executor: &TaskExecutor,
+    task: F,
+    name: &'static str,
+    failure: &'static str,
+) -> (JoinHandle<()>, AbortHandle)
+where
+    F: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let mut shutdown_sender = executor.shutdown_sender();
+    let handle = tokio::spawn(task);
+    let abort = handle.abort_handle();
+
+    let join = tokio::spawn(async move {
+        match handle.await {
+            Ok(Ok(())) => {
+                debug!(task = name, "Supervised task completed");
+                return;
+            }
+            Ok(Err(e)) => error!(task = name, error = ?e, "Supervised task failed"),
+            Err(e) if e.is_cancelled() => {
+                debug!(task = name, "Supervised task aborted");
+                return;
+            }
+            Err(e) => error!(task = name, error = ?e, "Supervised task panicked"),
+        }
+
+        if let Err(e) = shutdown_sender.try_send(ShutdownReason::Failure(failure)) {
+            error!(task = name, error = %e, "Failed to request shutdown");
+        }
+    });
+    (join, abort)
+}
+
 pub struct Environment {
     #[allow(unused)]
     signal: Signal,
@@ -25,12 +66,23 @@ pub struct Environment {
 }
 
 impl Environment {
-    pub async fn wait_shutdown_signal(mut self) {
+    /// Waits for a shutdown signal, calling `on_reload` on every SIGHUP.
+    /// Returns the reason of an internal shutdown, or `None` on SIGTERM and
+    /// SIGINT.
+    ///
+    /// Tasks spawned on the executor keep running until the environment is
+    /// dropped, so that ongoing work can be drained after the signal.
+    pub async fn wait_shutdown_signal(
+        &mut self,
+        mut on_reload: impl FnMut(),
+    ) -> Option<ShutdownReason> {
         let mut sig_term = match signal(SignalKind::terminate()) {
             Ok(x) => x,
             Err(e) => {
                 error!(target: "Shutdown Handler", error = %e, "Could not register SIGTERM handler");
-                return;
+                return Some(ShutdownReason::Failure(
+                    "signal handler registration failed",
+                ));
             }
         };
 
@@ -38,7 +90,9 @@ impl Environment {
             Ok(x) => x,
             Err(e) => {
                 error!(target: "Shutdown Handler", error = %e, "Could not register SIGINT handler");
-                return;
+                return Some(ShutdownReason::Failure(
+                    "signal handler registration failed",
+                ));
             }
         };
 
@@ -46,7 +100,9 @@ impl Environment {
             Ok(x) => x,
             Err(e) => {
                 error!(target: "Shutdown Handler", error = %e, "Could not register SIGHUP handler");
-                return;
+                return Some(ShutdownReason::Failure(
+                    "signal handler registration failed",
+                ));
             }
         };
 
@@ -58,7 +114,7 @@ impl Environment {
                     match res {
                         Some(reason) => {
                             info!(target: "Shutdown Handler", reason = reason.message(), "Internal shutdown received");
-                            return;
+                            return Some(reason);
                         },
                         None => {
                             debug!(target: "Shutdown Handler", "Internal shutdown channel closed");
@@ -68,15 +124,15 @@ impl Environment {
                 },
                 _ = sig_term.recv() => {
                     info!(target: "Shutdown Handler", "Received SIGTERM");
-                    return;
+                    return None;
                 }
                 _ = sig_int.recv() => {
                     info!(target: "Shutdown Handler", "Received SIGINT");
-                    return;
+                    return None;
                 }
                 _ = sig_hup.recv() => {
-                    info!(target: "Shutdown Handler", "Received SIGHUP");
-                    return;
+                    info!(target: "Shutdown Handler", "Received SIGHUP, reloading config");
+                    on_reload();
                 }
             }
         }
@@ -1,10 +1,30 @@
 #[macro_use]
 extern crate tracing;
 
-use std::vec;
+mod slice;
+mod source;
 
-use anyhow::{bail, Result};
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+    vec,
+};
+
+use anyhow::{bail, Context, Result};
+use opentelemetry::{global, propagation::Injector};
 use signer::{signer_client::SignerClient, BatchRetrieveRequest, RetrieveRequest};
+use tonic::{
+    metadata::{MetadataKey, MetadataMap, MetadataValue},
+    transport::{Channel, Endpoint},
+    Code,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub use slice::{decode_batches, decode_slice, SliceError};
+pub use source::{
+    CachingSource, FaultySource, RecordingSource, SignerEndpoint, SliceCall, SliceFault,
+    SliceSource,
+};
 
 pub mod signer {
     tonic::include_proto!("signer");
@@ -12,6 +32,24 @@ pub mod signer {
 
 const MESSAGE_SIZE_LIMIT: usize = 1024 * 1024 * 1024; // 1G
 
+#[derive(Clone, Debug)]
+pub struct SignerConfig {
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+    pub max_message_size: usize,
+}
+
+impl Default for SignerConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            max_message_size: MESSAGE_SIZE_LIMIT,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct RetrieveParam {
     pub epoch: u64,
     pub quorum_id: u64,
@@ -19,11 +57,20 @@ pub struct RetrieveParam {
     pub row_indexes: Vec<u32>,
 }
 
-pub struct SignerProvider {}
+pub struct SignerProvider {
+    config: RwLock<SignerConfig>,
+}
 
 impl SignerProvider {
-    pub fn new() -> Result<Self> {
-        Ok(Self {})
+    pub fn new(config: SignerConfig) -> Result<Self> {
+        Ok(Self {
+            config: RwLock::new(config),
+        })
+    }
+
+    /// Applies to requests started after the update.
+    pub fn update_config(&self, config: SignerConfig) {
+        *self.config.write().unwrap() = config;
     }
 
     pub async fn get_slices(
@@ -32,12 +79,13 @@ impl SignerProvider {
         retrieve_params: Vec<RetrieveParam>,
     ) -> Result<Vec<Vec<Vec<u8>>>> {
         info!("request slices from {:?}", socket);
-        let mut client = SignerClient::connect(socket.clone())
-            .await?
-            .max_decoding_message_size(MESSAGE_SIZE_LIMIT)
-            .max_encoding_message_size(MESSAGE_SIZE_LIMIT);
+        let mut client = self.connect(&socket).await?;
 
-        let request = tonic::Request::new(BatchRetrieveRequest {
+        let rows: Vec<Vec<u32>> = retrieve_params
+            .iter()
+            .map(|p| p.row_indexes.clone())
+            .collect();
+        let mut request = tonic::Request::new(BatchRetrieveRequest {
             requests: retrieve_params
                 .into_iter()
                 .map(|p| RetrieveRequest {
@@ -48,6 +96,11 @@ impl SignerProvider {
                 })
                 .collect(),
         });
+        // continue the caller's trace on the signer
+        let context = tracing::Span::current().context();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut MetadataInjector(request.metadata_mut()))
+        });
 
         let response = match client.batch_retrieve(request).await {
             Ok(v) => v.into_inner(),
@@ -56,18 +109,65 @@ impl SignerProvider {
             }
         };
 
-        let mut res = vec![];
-        for slices in response.encoded_slice.into_iter() {
-            let mut s = vec![];
-            for mut slice in slices.encoded_slice.into_iter() {
-                slice.drain(0..8); // first 8 byte is length
-                s.push(slice);
-            }
+        let batches = response
+            .encoded_slice
+            .into_iter()
+            .map(|slices| slices.encoded_slice)
+            .collect();
+        decode_batches(&rows, batches)
+            .with_context(|| format!("malformed reply of socket {:?}", socket))
+    }
 
-            res.push(s);
+    /// Probes a signer with an empty batch request, returning the round trip
+    /// time including the connection setup. Any answer of the signer, even an
+    /// error status, counts as reachable.
+    pub async fn ping(&self, socket: &str) -> Result<Duration> {
+        let ts = Instant::now();
+        let mut client = self.connect(socket).await?;
+        match client
+            .batch_retrieve(BatchRetrieveRequest { requests: vec![] })
+            .await
+        {
+            Err(status) if matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded) => {
+                bail!("socket: {:?}, err: {:?}", socket, status)
+            }
+            _ => Ok(ts.elapsed()),
         }
+    }
+
+    async fn connect(&self, socket: &str) -> Result<SignerClient<Channel>> {
+        let config = self.config.read().unwrap().clone();
+        let channel = Endpoint::from_shared(socket.to_owned())?
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.request_timeout)
+            .connect()
+            .await?;
+
+        Ok(SignerClient::new(channel)
+            .max_decoding_message_size(config.max_message_size)
+            .max_encoding_message_size(config.max_message_size))
+    }
+}
 
-        Ok(res)
+/// URL of a socket registered on chain, which usually comes without scheme.
+pub fn socket_url(socket: &str) -> String {
+    if socket.to_lowercase().starts_with("http://") {
+        socket.to_owned()
+    } else {
+        format!("http://{}", socket)
+    }
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl<'a> Injector for MetadataInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::try_from(value),
+        ) {
+            self.0.insert(key, value);
+        }
     }
 }
 
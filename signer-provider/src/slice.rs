@@ -0,0 +1,137 @@
+//! Wire format of the slices in a `BatchRetrieveReply`: one batch of slices
+//! per request, one slice per requested row, each slice being the encoded row
+//! preceded by its length in bytes as a little endian `u64`.
+
+use thiserror::Error;
+
+const LENGTH_PREFIX_SIZE: usize = 8;
+
+/// A malformed reply of a signer.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum SliceError {
+    #[error("{got} slice batches for {expected} requests")]
+    BatchCount { expected: usize, got: usize },
+    #[error("request {request} got {got} slices for {expected} rows")]
+    RowCount {
+        request: usize,
+        expected: usize,
+        got: usize,
+    },
+    #[error("slice of row {row} is {len} bytes, too short for the length prefix")]
+    MissingPrefix { row: u32, len: usize },
+    #[error("slice of row {row} declares {declared} bytes, carries {actual}")]
+    LengthMismatch {
+        row: u32,
+        declared: u64,
+        actual: usize,
+    },
+}
+
+/// Strips the length prefix of the slice of `row`, checking it against the
+/// bytes that follow.
+pub fn decode_slice(row: u32, mut slice: Vec<u8>) -> Result<Vec<u8>, SliceError> {
+    if slice.len() < LENGTH_PREFIX_SIZE {
+        return Err(SliceError::MissingPrefix {
+            row,
+            len: slice.len(),
+        });
+    }
+
+    let mut prefix = [0u8; LENGTH_PREFIX_SIZE];
+    prefix.copy_from_slice(&slice[..LENGTH_PREFIX_SIZE]);
+    let declared = u64::from_le_bytes(prefix);
+    let actual = slice.len() - LENGTH_PREFIX_SIZE;
+    if declared != actual as u64 {
+        return Err(SliceError::LengthMismatch {
+            row,
+            declared,
+            actual,
+        });
+    }
+
+    slice.drain(..LENGTH_PREFIX_SIZE);
+    Ok(slice)
+}
+
+/// Decodes the batches of a reply to requests for `rows`, which must hold
+/// exactly one slice per requested row.
+pub fn decode_batches(
+    rows: &[Vec<u32>],
+    batches: Vec<Vec<Vec<u8>>>,
+) -> Result<Vec<Vec<Vec<u8>>>, SliceError> {
+    if batches.len() != rows.len() {
+        return Err(SliceError::BatchCount {
+            expected: rows.len(),
+            got: batches.len(),
+        });
+    }
+
+    let mut res = Vec::with_capacity(batches.len());
+    for (request, (rows, slices)) in rows.iter().zip(batches).enumerate() {
+        if slices.len() != rows.len() {
+            return Err(SliceError::RowCount {
+                request,
+                expected: rows.len(),
+                got: slices.len(),
+            });
+        }
+
+        let slices = rows
+            .iter()
+            .zip(slices)
+            .map(|(row, slice)| decode_slice(*row, slice))
+            .collect::<Result<_, _>>()?;
+        res.push(slices);
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(row: &[u8]) -> Vec<u8> {
+        let mut slice = (row.len() as u64).to_le_bytes().to_vec();
+        slice.extend_from_slice(row);
+        slice
+    }
+
+    #[test]
+    fn malformed_replies_are_rejected() {
+        let rows = vec![vec![2, 3]];
+        let decoded = decode_batches(&rows, vec![vec![encode(&[2; 4]), encode(&[3; 4])]]);
+        assert_eq!(decoded, Ok(vec![vec![vec![2; 4], vec![3; 4]]]));
+
+        assert_eq!(
+            decode_batches(&rows, vec![]),
+            Err(SliceError::BatchCount {
+                expected: 1,
+                got: 0
+            })
+        );
+        assert_eq!(
+            decode_batches(&rows, vec![vec![encode(&[2; 4])]]),
+            Err(SliceError::RowCount {
+                request: 0,
+                expected: 2,
+                got: 1
+            })
+        );
+
+        let mut truncated = encode(&[3; 4]);
+        truncated.pop();
+        assert_eq!(
+            decode_batches(&rows, vec![vec![encode(&[2; 4]), truncated]]),
+            Err(SliceError::LengthMismatch {
+                row: 3,
+                declared: 4,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            decode_slice(7, vec![1, 0, 0]),
+            Err(SliceError::MissingPrefix { row: 7, len: 3 })
+        );
+    }
+}
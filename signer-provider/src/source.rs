@@ -0,0 +1,429 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use ethers::types::H160;
+
+use crate::{socket_url, RetrieveParam, SignerProvider};
+
+/// A DA signer, as registered on chain.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SignerEndpoint {
+    pub address: H160,
+    /// Socket as registered, usually without scheme.
+    pub socket: String,
+}
+
+/// Where the slices of the signers are fetched from.
+#[async_trait]
+pub trait SliceSource: Send + Sync {
+    /// Slices of the rows of each param, in the order of `params` and their
+    /// `row_indexes`, without the length prefix. A malformed reply of the
+    /// signer fails with a [`SliceError`](crate::SliceError).
+    async fn get_slices(
+        &self,
+        signer: &SignerEndpoint,
+        params: Vec<RetrieveParam>,
+    ) -> Result<Vec<Vec<Vec<u8>>>>;
+
+    /// Tells that `slices`, answered by `signer` for the rows of `param`,
+    /// were verified and used by the caller.
+    fn accept(&self, _signer: &SignerEndpoint, _param: &RetrieveParam, _slices: &[Vec<u8>]) {}
+}
+
+#[async_trait]
+impl SliceSource for SignerProvider {
+    async fn get_slices(
+        &self,
+        signer: &SignerEndpoint,
+        params: Vec<RetrieveParam>,
+    ) -> Result<Vec<Vec<Vec<u8>>>> {
+        SignerProvider::get_slices(self, socket_url(&signer.socket), params).await
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct SliceKey {
+    address: H160,
+    epoch: u64,
+    quorum_id: u64,
+    storage_root: Vec<u8>,
+    row: u32,
+}
+
+struct SliceCache {
+    slices: HashMap<SliceKey, Vec<u8>>,
+    // insertion order, oldest first
+    keys: VecDeque<SliceKey>,
+}
+
+/// Keeps the last `capacity` accepted slices, all signers together, so
+/// repeated requests for the same rows are answered without asking the
+/// signers again. Slices the caller did not accept are never kept, as they
+/// may be wrong.
+pub struct CachingSource<S> {
+    inner: S,
+    capacity: usize,
+    cache: Mutex<SliceCache>,
+}
+
+impl<S: SliceSource> CachingSource<S> {
+    pub fn new(inner: S, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            cache: Mutex::new(SliceCache {
+                slices: HashMap::new(),
+                keys: VecDeque::new(),
+            }),
+        }
+    }
+
+    fn keys(signer: &SignerEndpoint, param: &RetrieveParam) -> Vec<SliceKey> {
+        param
+            .row_indexes
+            .iter()
+            .map(|row| SliceKey {
+                address: signer.address,
+                epoch: param.epoch,
+                quorum_id: param.quorum_id,
+                storage_root: param.storage_root.clone(),
+                row: *row,
+            })
+            .collect()
+    }
+
+    fn lookup(&self, keys: &[SliceKey]) -> Option<Vec<Vec<u8>>> {
+        let cache = self.cache.lock().unwrap();
+        keys.iter()
+            .map(|key| cache.slices.get(key).cloned())
+            .collect()
+    }
+
+    fn insert(&self, keys: Vec<SliceKey>, slices: &[Vec<u8>]) {
+        let mut cache = self.cache.lock().unwrap();
+        for (key, slice) in keys.into_iter().zip(slices.iter()) {
+            if cache.slices.insert(key.clone(), slice.clone()).is_none() {
+                cache.keys.push_back(key);
+            }
+        }
+        while cache.keys.len() > self.capacity {
+            if let Some(key) = cache.keys.pop_front() {
+                cache.slices.remove(&key);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S: SliceSource> SliceSource for CachingSource<S> {
+    async fn get_slices(
+        &self,
+        signer: &SignerEndpoint,
+        params: Vec<RetrieveParam>,
+    ) -> Result<Vec<Vec<Vec<u8>>>> {
+        let keys: Vec<_> = params.iter().map(|p| Self::keys(signer, p)).collect();
+        let mut res: Vec<_> = keys.iter().map(|keys| self.lookup(keys)).collect();
+
+        // params with any row missing are fetched as a whole
+        let missing: Vec<usize> = (0..params.len()).filter(|i| res[*i].is_none()).collect();
+        if missing.is_empty() {
+            debug!(signer = ?signer.address, "slices served from cache");
+            return Ok(res.into_iter().flatten().collect());
+        }
+
+        let mut params: Vec<Option<RetrieveParam>> = params.into_iter().map(Some).collect();
+        let fetch = missing.iter().filter_map(|i| params[*i].take()).collect();
+        let fetched = self.inner.get_slices(signer, fetch).await?;
+        for (i, slices) in missing.into_iter().zip(fetched) {
+            res[i] = Some(slices);
+        }
+
+        // like the signer, a short answer leaves out the trailing params
+        Ok(res
+            .into_iter()
+            .take_while(Option::is_some)
+            .flatten()
+            .collect())
+    }
+
+    fn accept(&self, signer: &SignerEndpoint, param: &RetrieveParam, slices: &[Vec<u8>]) {
+        let keys = Self::keys(signer, param);
+        if keys.len() == slices.len() {
+            self.insert(keys, slices);
+        }
+        self.inner.accept(signer, param, slices);
+    }
+}
+
+/// One call recorded by a [`RecordingSource`].
+#[derive(Clone, Debug)]
+pub struct SliceCall {
+    pub signer: SignerEndpoint,
+    /// Rows requested, for each param.
+    pub rows: Vec<Vec<u32>>,
+    /// Slices received for each param, or the error.
+    pub result: std::result::Result<Vec<usize>, String>,
+    pub latency: Duration,
+}
+
+/// Records every call passed on to the inner source, and the rows accepted.
+pub struct RecordingSource<S> {
+    inner: S,
+    calls: Mutex<Vec<SliceCall>>,
+    accepted: Mutex<Vec<(H160, Vec<u32>)>>,
+}
+
+impl<S: SliceSource> RecordingSource<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            calls: Mutex::new(vec![]),
+            accepted: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn calls(&self) -> Vec<SliceCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Rows accepted, with the signer that served them.
+    pub fn accepted(&self) -> Vec<(H160, Vec<u32>)> {
+        self.accepted.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl<S: SliceSource> SliceSource for RecordingSource<S> {
+    async fn get_slices(
+        &self,
+        signer: &SignerEndpoint,
+        params: Vec<RetrieveParam>,
+    ) -> Result<Vec<Vec<Vec<u8>>>> {
+        let rows = params.iter().map(|p| p.row_indexes.clone()).collect();
+        let ts = Instant::now();
+        let res = self.inner.get_slices(signer, params).await;
+
+        self.calls.lock().unwrap().push(SliceCall {
+            signer: signer.clone(),
+            rows,
+            result: match &res {
+                Ok(slices) => Ok(slices.iter().map(Vec::len).collect()),
+                Err(e) => Err(e.to_string()),
+            },
+            latency: ts.elapsed(),
+        });
+        res
+    }
+
+    fn accept(&self, signer: &SignerEndpoint, param: &RetrieveParam, slices: &[Vec<u8>]) {
+        self.accepted
+            .lock()
+            .unwrap()
+            .push((signer.address, param.row_indexes.clone()));
+        self.inner.accept(signer, param, slices);
+    }
+}
+
+/// Misbehaviour injected by a [`FaultySource`] for a signer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SliceFault {
+    /// Fail the call.
+    Error(String),
+    /// Answer without any slice.
+    Empty,
+    /// Answer after a delay.
+    Delay(Duration),
+    /// Flip every byte of the slices.
+    Corrupt,
+    /// Flip the byte at this offset of every slice, leaving the rest intact.
+    FlipByte(usize),
+    /// Leave out the last rows of each param.
+    MissingRows(usize),
+}
+
+/// Damages the answers of chosen signers, the others are passed through.
+pub struct FaultySource<S> {
+    inner: S,
+    faults: HashMap<H160, SliceFault>,
+}
+
+impl<S: SliceSource> FaultySource<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            faults: HashMap::new(),
+        }
+    }
+
+    pub fn with_fault(mut self, address: H160, fault: SliceFault) -> Self {
+        self.faults.insert(address, fault);
+        self
+    }
+}
+
+#[async_trait]
+impl<S: SliceSource> SliceSource for FaultySource<S> {
+    async fn get_slices(
+        &self,
+        signer: &SignerEndpoint,
+        params: Vec<RetrieveParam>,
+    ) -> Result<Vec<Vec<Vec<u8>>>> {
+        let fault = match self.faults.get(&signer.address) {
+            Some(fault) => fault,
+            None => return self.inner.get_slices(signer, params).await,
+        };
+
+        match fault {
+            SliceFault::Error(e) => bail!("socket: {:?}, err: {}", signer.socket, e),
+            SliceFault::Empty => Ok(vec![]),
+            SliceFault::Delay(delay) => {
+                tokio::time::sleep(*delay).await;
+                self.inner.get_slices(signer, params).await
+            }
+            SliceFault::Corrupt => {
+                let mut res = self.inner.get_slices(signer, params).await?;
+                res.iter_mut().flatten().flatten().for_each(|b| *b = !*b);
+                Ok(res)
+            }
+            SliceFault::FlipByte(offset) => {
+                let mut res = self.inner.get_slices(signer, params).await?;
+                for slice in res.iter_mut().flatten() {
+                    if let Some(b) = slice.get_mut(*offset) {
+                        *b = !*b;
+                    }
+                }
+                Ok(res)
+            }
+            SliceFault::MissingRows(n) => {
+                let mut res = self.inner.get_slices(signer, params).await?;
+                for slices in res.iter_mut() {
+                    slices.truncate(slices.len().saturating_sub(*n));
+                }
+                Ok(res)
+            }
+        }
+    }
+
+    fn accept(&self, signer: &SignerEndpoint, param: &RetrieveParam, slices: &[Vec<u8>]) {
+        self.inner.accept(signer, param, slices);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every row with its index.
+    struct RowSource;
+
+    #[async_trait]
+    impl SliceSource for RowSource {
+        async fn get_slices(
+            &self,
+            _signer: &SignerEndpoint,
+            params: Vec<RetrieveParam>,
+        ) -> Result<Vec<Vec<Vec<u8>>>> {
+            Ok(params
+                .into_iter()
+                .map(|p| p.row_indexes.iter().map(|i| vec![*i as u8; 4]).collect())
+                .collect())
+        }
+    }
+
+    fn param(rows: Vec<u32>) -> RetrieveParam {
+        RetrieveParam {
+            epoch: 1,
+            quorum_id: 0,
+            storage_root: vec![7; 32],
+            row_indexes: rows,
+        }
+    }
+
+    #[tokio::test]
+    async fn decorators_compose() {
+        let (honest, faulty) = (
+            SignerEndpoint {
+                address: H160::repeat_byte(1),
+                socket: "127.0.0.1:1".into(),
+            },
+            SignerEndpoint {
+                address: H160::repeat_byte(2),
+                socket: "127.0.0.1:2".into(),
+            },
+        );
+        let source = CachingSource::new(
+            RecordingSource::new(
+                FaultySource::new(RowSource).with_fault(faulty.address, SliceFault::MissingRows(1)),
+            ),
+            16,
+        );
+
+        let slices = source
+            .get_slices(&honest, vec![param(vec![3, 5])])
+            .await
+            .unwrap();
+        assert_eq!(slices, vec![vec![vec![3; 4], vec![5; 4]]]);
+        // not accepted yet, so asked again
+        let slices = source
+            .get_slices(&honest, vec![param(vec![5])])
+            .await
+            .unwrap();
+        source.accept(&honest, &param(vec![5]), &slices[0]);
+        // served from cache
+        source
+            .get_slices(&honest, vec![param(vec![5])])
+            .await
+            .unwrap();
+
+        let slices = source
+            .get_slices(&faulty, vec![param(vec![3, 5])])
+            .await
+            .unwrap();
+        assert_eq!(slices, vec![vec![vec![3; 4]]]);
+        // incomplete answers are not cached
+        source.accept(&faulty, &param(vec![3, 5]), &slices[0]);
+        source
+            .get_slices(&faulty, vec![param(vec![3, 5])])
+            .await
+            .unwrap();
+
+        let calls = source.inner.calls();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[3].result, Ok(vec![1]));
+    }
+
+    #[tokio::test]
+    async fn cache_capacity_is_shared_across_signers() {
+        let signers: Vec<_> = (1..=3)
+            .map(|i| SignerEndpoint {
+                address: H160::repeat_byte(i),
+                socket: format!("127.0.0.1:{}", i),
+            })
+            .collect();
+        let source = CachingSource::new(RecordingSource::new(RowSource), 2);
+
+        for signer in &signers {
+            let slices = source
+                .get_slices(signer, vec![param(vec![1])])
+                .await
+                .unwrap();
+            source.accept(signer, &param(vec![1]), &slices[0]);
+        }
+        // the slice of the first signer was evicted, the others are kept
+        for signer in signers.iter().rev() {
+            source
+                .get_slices(signer, vec![param(vec![1])])
+                .await
+                .unwrap();
+        }
+
+        let calls = source.inner.calls();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[3].signer, signers[0]);
+    }
+}